@@ -0,0 +1,607 @@
+use crate::cpu_emu::{Format, Opcode, Operands, Rom, Slot, Spec};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, column: usize, message: String) -> Self {
+        Self {
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// An assembled program: the encoded words and the address of every label.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Program {
+    pub words: Vec<u16>,
+    pub labels: BTreeMap<String, usize>,
+    /// 1-based source line of each word; empty for images loaded from disk.
+    pub lines: Vec<usize>,
+}
+
+impl Program {
+    pub fn into_rom(self) -> Rom {
+        Rom::new(self.words)
+    }
+}
+
+/// Assembles mnemonic source (`mov r0, r1`, `ldl r3, 10`, `je 14`, `ld r0, [r1]`, `hlt`)
+/// into a ROM image.
+///
+/// One instruction per line; `;` starts a comment. Immediates may be written in
+/// decimal, `0x` hex or `0b` binary (both may group digits with `_`), or name a
+/// label (`loop:`) or a `.equ NAME value` constant. `.word value` emits a raw
+/// 16-bit word.
+pub fn assemble(source: &str) -> Result<Rom, AsmError> {
+    assemble_program(source).map(Program::into_rom)
+}
+
+/// Like [`assemble`], but keeps the label table alongside the encoded words.
+///
+/// Labels are collected in a first pass so jumps may refer forward; `.equ`
+/// values may only refer to symbols defined above them.
+pub fn assemble_program(source: &str) -> Result<Program, AsmError> {
+    let lines: Vec<Line> = source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| Line::new(index + 1, text))
+        .collect();
+
+    let mut symbols = Symbols::default();
+    let mut instructions = Vec::new();
+    for line in &lines {
+        let (labels, statement) = line.parse()?;
+        for label in labels {
+            symbols.define(line, label, Symbol::Label(instructions.len()))?;
+        }
+        match statement {
+            Statement::Empty => {}
+            Statement::Equ(name, value) => {
+                let value = line.value(value, &symbols)?;
+                symbols.define(line, name, Symbol::Constant(value))?;
+            }
+            Statement::Instruction(mnemonic, operands) => {
+                instructions.push((line, mnemonic, operands))
+            }
+        }
+    }
+
+    let mut words = Vec::with_capacity(instructions.len());
+    let lines = instructions
+        .iter()
+        .map(|(line, _, _)| line.number)
+        .collect();
+    for (line, mnemonic, operands) in instructions {
+        let word = if mnemonic.text.eq_ignore_ascii_case(".word") {
+            line.word(mnemonic, &operands, &symbols)?
+        } else {
+            line.instruction(mnemonic, &operands, &symbols)?.encode()
+        };
+        words.push(word);
+    }
+
+    Ok(Program {
+        words,
+        labels: symbols.labels(),
+        lines,
+    })
+}
+
+/// A source token together with its 1-based column.
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Symbol {
+    Label(usize),
+    Constant(u32),
+}
+
+#[derive(Debug, Default)]
+struct Symbols {
+    table: HashMap<String, Symbol>,
+}
+
+impl Symbols {
+    fn define(&mut self, line: &Line, name: Token, symbol: Symbol) -> Result<(), AsmError> {
+        if !is_identifier(name.text) {
+            return Err(line.error(name.column, format!("invalid symbol name `{}`", name.text)));
+        }
+        if self.table.insert(name.text.to_string(), symbol).is_some() {
+            return Err(line.error(
+                name.column,
+                format!("symbol `{}` is already defined", name.text),
+            ));
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Option<Symbol> {
+        self.table.get(name).copied()
+    }
+
+    fn labels(&self) -> BTreeMap<String, usize> {
+        self.table
+            .iter()
+            .filter_map(|(name, symbol)| match symbol {
+                Symbol::Label(addr) => Some((name.clone(), *addr)),
+                Symbol::Constant(_) => None,
+            })
+            .collect()
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+enum Statement<'a> {
+    Empty,
+    Equ(Token<'a>, Token<'a>),
+    Instruction(Token<'a>, Vec<Token<'a>>),
+}
+
+struct Line<'a> {
+    number: usize,
+    text: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn new(number: usize, text: &'a str) -> Self {
+        let text = match text.find(';') {
+            Some(pos) => &text[..pos],
+            None => text,
+        };
+        Self { number, text }
+    }
+
+    fn error(&self, column: usize, message: String) -> AsmError {
+        AsmError::new(self.number, column, message)
+    }
+
+    fn token(&self, text: &'a str) -> Token<'a> {
+        let trimmed = text.trim_start();
+        let offset = trimmed.as_ptr() as usize - self.text.as_ptr() as usize;
+        Token {
+            text: trimmed.trim_end(),
+            column: self.text[..offset].chars().count() + 1,
+        }
+    }
+
+    /// Splits the line into its leading labels and the statement that follows them.
+    fn parse(&self) -> Result<(Vec<Token<'a>>, Statement<'a>), AsmError> {
+        let mut body = self.text.trim_start();
+        let mut labels = Vec::new();
+        while let Some(colon) = body.find(':') {
+            let label = self.token(&body[..colon]);
+            if label.text.contains(char::is_whitespace) {
+                break;
+            }
+            labels.push(label);
+            body = body[colon + 1..].trim_start();
+        }
+
+        if body.is_empty() {
+            return Ok((labels, Statement::Empty));
+        }
+
+        let split = body.find(char::is_whitespace).unwrap_or(body.len());
+        let mnemonic = self.token(&body[..split]);
+        let rest = &body[split..];
+        let operands: Vec<Token> = if rest.trim().is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(|text| self.token(text)).collect()
+        };
+
+        if let Some(empty) = operands.iter().find(|op| op.text.is_empty()) {
+            return Err(self.error(empty.column, "expected operand".to_string()));
+        }
+
+        let statement = if mnemonic.text.eq_ignore_ascii_case(".equ") {
+            self.equ(mnemonic, rest)?
+        } else {
+            Statement::Instruction(mnemonic, operands)
+        };
+
+        Ok((labels, statement))
+    }
+
+    /// Parses the operands of `.equ NAME value` (a comma after the name is optional).
+    fn equ(&self, directive: Token<'a>, rest: &'a str) -> Result<Statement<'a>, AsmError> {
+        let rest = rest.trim_start();
+        let split = rest
+            .find(|c: char| c.is_whitespace() || c == ',')
+            .unwrap_or(rest.len());
+        let name = self.token(&rest[..split]);
+        let value = self.token(rest[split..].trim_start().trim_start_matches(','));
+
+        if name.text.is_empty() || value.text.is_empty() {
+            return Err(self.error(
+                directive.column,
+                "`.equ` expects a name and a value".to_string(),
+            ));
+        }
+
+        Ok(Statement::Equ(name, value))
+    }
+
+    fn word(
+        &self,
+        directive: Token,
+        operands: &[Token],
+        symbols: &Symbols,
+    ) -> Result<u16, AsmError> {
+        if operands.len() != 1 {
+            return Err(self.error(
+                directive.column,
+                format!("`.word` expects 1 operand, found {}", operands.len()),
+            ));
+        }
+
+        let value = self.value(operands[0], symbols)?;
+        if value > 0xffff {
+            return Err(self.error(
+                operands[0].column,
+                format!("value {} does not fit in 16 bits", value),
+            ));
+        }
+        Ok(value as u16)
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: Token,
+        operands: &[Token],
+        symbols: &Symbols,
+    ) -> Result<Opcode, AsmError> {
+        let indirect = operands.last().is_some_and(|op| op.text.starts_with('['));
+        let spec = Spec::by_syntax(mnemonic.text, indirect).ok_or_else(|| {
+            self.error(
+                mnemonic.column,
+                format!("unknown mnemonic `{}`", mnemonic.text),
+            )
+        })?;
+
+        let arity = match spec.format {
+            Format::RegReg | Format::RegData | Format::RegAddr | Format::RegInd => 2,
+            Format::Reg | Format::Addr | Format::Ind => 1,
+            Format::Bare => 0,
+        };
+        if operands.len() != arity {
+            let column = operands
+                .get(arity)
+                .map_or(mnemonic.column, |extra| extra.column);
+            return Err(self.error(
+                column,
+                format!(
+                    "`{}` expects {} operand(s), found {}",
+                    spec.mnemonic,
+                    arity,
+                    operands.len()
+                ),
+            ));
+        }
+
+        let reg = |index: usize| self.register(operands[index]);
+        let byte = |index: usize| self.byte(operands[index], symbols);
+        let address = |index: usize| self.address_register(operands[index]);
+        let operands = match spec.format {
+            Format::RegReg => Operands::RegReg(reg(0)?, reg(1)?),
+            Format::Reg => Operands::Reg(reg(0)?),
+            Format::RegData => Operands::RegData(reg(0)?, byte(1)?),
+            Format::RegAddr => Operands::RegAddr(reg(0)?, byte(1)? as usize),
+            Format::Addr => Operands::Addr(byte(0)? as usize),
+            Format::Bare => Operands::Bare,
+            Format::RegInd => Operands::RegInd(reg(0)?, address(1)?),
+            Format::Ind => Operands::Ind(address(0)?),
+        };
+
+        Ok(Opcode::from_parts(spec.code, operands).expect("operands built from the spec format"))
+    }
+
+    /// Parses `[rN]`, the address operand of the indirect `ld`, `st` and `jmp`.
+    fn address_register(&self, token: Token) -> Result<Slot, AsmError> {
+        let inner = token
+            .text
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .ok_or_else(|| {
+                self.error(
+                    token.column,
+                    format!("expected `[rN]`, found `{}`", token.text),
+                )
+            })?;
+        self.register(self.token(inner))
+    }
+
+    fn register(&self, token: Token) -> Result<Slot, AsmError> {
+        let text = token.text.to_ascii_lowercase();
+        let index = text
+            .strip_prefix('r')
+            .and_then(|digits| digits.parse::<u16>().ok())
+            .ok_or_else(|| {
+                self.error(
+                    token.column,
+                    format!("expected register, found `{}`", token.text),
+                )
+            })?;
+
+        Slot::try_from(index).map_err(|_| {
+            self.error(
+                token.column,
+                format!("register `{}` out of range (r0..r7)", token.text),
+            )
+        })
+    }
+
+    /// Resolves an operand for the 8-bit `data`/`addr` field.
+    fn byte(&self, token: Token, symbols: &Symbols) -> Result<u16, AsmError> {
+        let value = self.value(token, symbols)?;
+        if value > 0xff {
+            let message = match symbols.get(token.text) {
+                Some(Symbol::Label(_)) => format!(
+                    "label `{}` resolves to address {}, outside 0..=255",
+                    token.text, value
+                ),
+                _ => format!("immediate {} does not fit in 8 bits (0..=255)", value),
+            };
+            return Err(self.error(token.column, message));
+        }
+        Ok(value as u16)
+    }
+
+    fn value(&self, token: Token, symbols: &Symbols) -> Result<u32, AsmError> {
+        if !is_identifier(token.text) {
+            return self.number(token);
+        }
+
+        match symbols.get(token.text) {
+            Some(Symbol::Label(addr)) => Ok(addr as u32),
+            Some(Symbol::Constant(value)) => Ok(value),
+            None => Err(self.error(token.column, format!("undefined symbol `{}`", token.text))),
+        }
+    }
+
+    fn number(&self, token: Token) -> Result<u32, AsmError> {
+        let text = token.text.to_ascii_lowercase();
+        let parsed = if let Some(hex) = text.strip_prefix("0x") {
+            u32::from_str_radix(&hex.replace('_', ""), 16)
+        } else if let Some(bin) = text.strip_prefix("0b") {
+            u32::from_str_radix(&bin.replace('_', ""), 2)
+        } else {
+            text.parse::<u32>()
+        };
+
+        parsed.map_err(|_| {
+            self.error(
+                token.column,
+                format!("expected number, found `{}`", token.text),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        let rom = assemble(source).unwrap();
+        (0..).map_while(|pc| rom.read(pc).ok()).collect()
+    }
+
+    #[test]
+    fn test_assemble_reg_reg() {
+        assert_eq!(words("mov r0, r1"), vec![0b0000_000_001_00000]);
+        assert_eq!(words("cmp r1, r2"), vec![0b1010_001_010_00000]);
+    }
+
+    #[test]
+    fn test_assemble_immediates() {
+        assert_eq!(words("ldl r3, 10"), vec![0b1000_011_00001010]);
+        assert_eq!(words("ldh r0, 0xb2"), vec![0b1001_000_10110010]);
+        assert_eq!(words("st r3, 0b0100_0000"), vec![0b1110_011_01000000]);
+        assert_eq!(words("ldh r1, 0xb_2"), vec![0b1001_001_10110010]);
+        assert_eq!(words("je 14"), vec![0b1011_000_00001110]);
+    }
+
+    #[test]
+    fn test_assemble_program() {
+        let source = "
+            ; sum 1..10 into ram[64]
+            ldl r1, 10
+            add r2, r0   ; trailing comment
+            SL  r2
+            hlt
+        ";
+        assert_eq!(
+            words(source),
+            vec![
+                0b1000_001_00001010,
+                0b0001_010_000_00000,
+                0b0101_010_000_00000,
+                0b1111_000_000_00000,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_stack() {
+        let source = "
+            push r1
+            call double
+            pop r1
+            hlt
+        double:
+            sl r0
+            ret
+        ";
+        assert_eq!(
+            words(source),
+            vec![
+                0b10101_001_00000000,
+                0b10111_000_00000100,
+                0b10110_001_00000000,
+                0b1111_000_000_00000,
+                0b0101_000_000_00000,
+                0b11000_000_000_00000,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_indirect() {
+        assert_eq!(words("ld r2, [r1]"), vec![0b11001_010_001_00000]);
+        assert_eq!(words("st r2, [ r7 ]"), vec![0b11010_010_111_00000]);
+        assert_eq!(words("jmp [r3]"), vec![0b11011_000_011_00000]);
+        assert_eq!(words("ld r2, 5"), vec![0b1101_010_00000101]);
+
+        let err = assemble("ld r2, [r9]").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 9));
+        let err = assemble("ld r2, [r1").unwrap_err();
+        assert_eq!(err.message, "expected `[rN]`, found `[r1`");
+    }
+
+    #[test]
+    fn test_unknown_mnemonic() {
+        let err = assemble("hlt\n  mul r0, r1").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.to_string(), "2:3: unknown mnemonic `mul`");
+    }
+
+    #[test]
+    fn test_register_out_of_range() {
+        let err = assemble("mov r0, r8").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 9));
+    }
+
+    #[test]
+    fn test_immediate_out_of_range() {
+        let err = assemble("ldl r0, 256").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 9));
+
+        let err = assemble("jmp 0x100").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 5));
+    }
+
+    #[test]
+    fn test_operand_count() {
+        let err = assemble("sl r0, r1").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 8));
+
+        let err = assemble("add r0,").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 8));
+
+        // Columns count characters, not bytes.
+        let err = assemble("sl \u{3bb}, r1").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 7));
+    }
+
+    #[test]
+    fn test_labels_and_forward_references() {
+        let source = "
+            .equ ONE 1
+            .equ LIMIT, 10
+            .equ SUM 64
+
+                ldl r0, ONE
+                ldl r1, LIMIT
+            loop:
+                add r2, r0
+                add r3, r2
+                st r3, SUM
+                cmp r1, r2
+                je done
+                jmp loop
+            done:
+                hlt
+        ";
+        let program = assemble_program(source).unwrap();
+
+        assert_eq!(program.labels["loop"], 2);
+        assert_eq!(program.labels["done"], 8);
+        assert!(!program.labels.contains_key("SUM"));
+        assert_eq!(program.lines, vec![6, 7, 9, 10, 11, 12, 13, 14, 16]);
+        assert_eq!(
+            program.words,
+            vec![
+                0b1000_000_00000001,
+                0b1000_001_00001010,
+                0b0001_010_000_00000,
+                0b0001_011_010_00000,
+                0b1110_011_01000000,
+                0b1010_001_010_00000,
+                0b1011_000_00001000,
+                0b1100_000_00000010,
+                0b1111_000_000_00000,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_label_on_same_line() {
+        let program = assemble_program("start: jmp start").unwrap();
+        assert_eq!(program.words, vec![0b1100_000_00000000]);
+    }
+
+    #[test]
+    fn test_word_directive() {
+        assert_eq!(
+            words(".word 0x8001\nhlt"),
+            vec![0x8001, 0b1111_000_000_00000]
+        );
+
+        let err = assemble(".word 0x10000").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 7));
+    }
+
+    #[test]
+    fn test_undefined_symbol() {
+        let err = assemble("jmp nowhere").unwrap_err();
+        assert_eq!(err.to_string(), "1:5: undefined symbol `nowhere`");
+    }
+
+    #[test]
+    fn test_duplicate_symbol() {
+        let err = assemble("a: hlt\n.equ a 3").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 6));
+    }
+
+    #[test]
+    fn test_label_out_of_range() {
+        let source = format!("jmp far\n{}far: hlt", "hlt\n".repeat(300));
+        let err = assemble(&source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:5: label `far` resolves to address 301, outside 0..=255"
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use rust_risc_emu::dap;
+use std::io::{self, BufReader};
+use std::{env, process};
+
+const USAGE: &str = "\
+Usage: rdap
+
+Speaks the Debug Adapter Protocol on stdin/stdout. Point an editor's debug
+adapter configuration at this executable; the `launch` request takes
+`program` (a .s/.asm source or ROM image), and optionally `format` and
+`stopOnEntry`.";
+
+fn main() {
+    if env::args().len() > 1 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    if let Err(err) = dap::serve(BufReader::new(io::stdin()), io::stdout()) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
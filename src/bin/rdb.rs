@@ -0,0 +1,73 @@
+use rust_risc_emu::debugger::{Debugger, Reply};
+use rust_risc_emu::image::{self, ImageFormat};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::{env, process};
+
+const USAGE: &str = "\
+Usage: rdb [--format <FMT>] <PROGRAM>
+
+Loads PROGRAM like rust_risc_emu does and starts an interactive debugger.
+Labels are available when PROGRAM is assembly source. Type `help` at the
+prompt for the list of commands.";
+
+fn parse<I: IntoIterator<Item = String>>(
+    args: I,
+) -> Result<(PathBuf, Option<ImageFormat>), String> {
+    let mut program = None;
+    let mut format = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = args.next().ok_or("--format needs a value")?;
+                format = Some(
+                    ImageFormat::from_name(&value)
+                        .ok_or_else(|| format!("unknown format `{}`", value))?,
+                );
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+            path if program.is_none() => program = Some(PathBuf::from(path)),
+            extra => return Err(format!("unexpected argument `{}`", extra)),
+        }
+    }
+
+    Ok((program.ok_or("missing PROGRAM")?, format))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let (path, format) = parse(args).unwrap_or_else(|msg| {
+        eprintln!("error: {}\n\n{}", msg, USAGE);
+        process::exit(2);
+    });
+    let program = image::open(&path, format).unwrap_or_else(|msg| {
+        eprintln!("error: {}", msg);
+        process::exit(1);
+    });
+
+    let mut debugger = Debugger::new(program);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("(rdb) ");
+        let _ = io::stdout().flush();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        match debugger.execute(&line) {
+            Ok(Reply::Output(text)) if text.is_empty() => {}
+            Ok(Reply::Output(text)) => println!("{}", text),
+            Ok(Reply::Quit) => break,
+            Err(msg) => println!("error: {}", msg),
+        }
+    }
+}
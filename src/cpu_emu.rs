@@ -1,108 +1,590 @@
+mod bus;
+mod error;
+mod flags;
+mod interrupt;
 mod ir;
 mod opcode;
 mod register;
 mod rom;
-
-use ir::InstructionRegister;
-use opcode::Opcode;
-use register::{GeneralRegister, Slot};
+mod state;
+mod timing;
+mod trace;
+mod watch;
+
+pub use bus::{AddressDecoder, Bus, Device, ROM_BASE};
+pub use error::EmuError;
+pub use flags::Flags;
+pub use interrupt::{InterruptController, Saved, LINES};
+pub use ir::InstructionRegister;
+pub use opcode::{Field, Format, Opcode, Operands, Spec, INSTRUCTIONS};
+use register::GeneralRegister;
+pub use register::Slot;
 pub use rom::Rom;
+pub use state::MachineState;
+use std::collections::BTreeSet;
+use std::mem;
+use std::ops::Range;
+pub use timing::{Cost, Timing};
+#[cfg(test)]
+pub use trace::Collect;
+pub use trace::{CsvTracer, JsonTracer, TextTracer, TraceEvent, Tracer};
+pub use watch::{WatchHit, Watchpoint};
 
 type Addr = usize;
 type Data = u16;
 
+/// Words of data RAM; the stack grows down from its top.
+pub const RAM_SIZE: usize = 256;
+
+/// Vector table entry for synchronous faults; line `n` uses entry `n + 1`.
+pub const TRAP_VECTOR: usize = 0;
+
+/// Why `step`, `run_for` or `run_until` handed control back to the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// `Hlt` was executed; further steps do nothing.
+    Halted,
+    /// A breakpoint address was reached or the `run_until` predicate matched;
+    /// the PC of the next instruction, which has not executed yet.
+    Breakpoint(Addr),
+    /// The instruction budget was used up.
+    BudgetExhausted,
+    /// The last instruction touched a watched address or register.
+    Watchpoint(WatchHit),
+    Fault(EmuError),
+}
+
+/// What one `run` call did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunStats {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+/// What `Add`, `Sub` and `Sl` do when the unsigned result does not fit in 16 bits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// Keep the low 16 bits and report the carry in `Flags`, like the hardware.
+    #[default]
+    Wrapping,
+    /// Stop with `EmuError::ArithmeticOverflow`, leaving registers and flags untouched.
+    Trapping,
+}
+
 #[derive(Debug)]
-pub struct CpuEmu {
+pub struct CpuEmu<B: Bus = AddressDecoder> {
     pc: usize,
+    /// The RAM address of the top of the stack; `RAM_SIZE` when it is empty.
+    sp: Addr,
     ir: InstructionRegister,
     register: GeneralRegister,
-    flag: bool,
-    rom: Rom,
-    ram: [u16; 256],
+    flags: Flags,
+    arithmetic: Arithmetic,
+    interrupts: InterruptController,
+    /// RAM address of the vector table, if interrupts and traps are wired up.
+    vectors: Option<Addr>,
+    halted: bool,
+    executed: u64,
+    cycles: u64,
+    timing: Timing,
+    bus: B,
+    tracer: Option<Box<dyn Tracer>>,
+    writes: Vec<(Addr, u16)>,
+    breakpoints: BTreeSet<Addr>,
+    watchpoints: Vec<Watchpoint>,
+    hit: Option<WatchHit>,
+    /// Where the last run stopped; a breakpoint there does not stop the next
+    /// run from resuming.
+    stopped_at: Option<Addr>,
 }
 
 impl CpuEmu {
     pub fn new(rom: Rom) -> Self {
+        Self::with_bus(AddressDecoder::new(rom))
+    }
+
+    pub fn rom(&self) -> &Rom {
+        self.bus.rom()
+    }
+
+    pub fn ram(&self, range: Range<Addr>) -> Result<&[u16], EmuError> {
+        let addr = range.end.saturating_sub(1).max(range.start);
+        self.bus
+            .ram()
+            .get(range)
+            .ok_or(EmuError::RamOutOfBounds { addr })
+    }
+
+    /// Copies `data` into RAM starting at `addr`.
+    pub fn write_ram(&mut self, addr: Addr, data: &[u16]) -> Result<(), EmuError> {
+        let end = addr
+            .checked_add(data.len())
+            .ok_or(EmuError::RamOutOfBounds { addr })?;
+        self.bus
+            .ram_mut()
+            .get_mut(addr..end)
+            .ok_or(EmuError::RamOutOfBounds {
+                addr: end.saturating_sub(1).max(addr),
+            })?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Overwrites ROM words starting at `addr`; the ROM keeps its length.
+    pub fn write_rom(&mut self, addr: Addr, data: &[u16]) -> Result<(), EmuError> {
+        for (offset, &word) in data.iter().enumerate() {
+            self.bus.rom_mut().write(addr + offset, word)?;
+        }
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> MachineState {
+        MachineState {
+            pc: self.pc,
+            sp: self.sp,
+            cycles: self.cycles,
+            registers: self.register.all(),
+            flags: self.flags,
+            halted: self.halted,
+            ram: self.bus.ram().to_vec(),
+        }
+    }
+}
+
+impl<B: Bus> CpuEmu<B> {
+    /// A CPU whose loads, stores and fetches go to `bus`.
+    pub fn with_bus(bus: B) -> Self {
         Self {
             register: GeneralRegister::new(),
             ir: InstructionRegister::new(),
             pc: 0,
-            flag: false,
-            rom: rom,
-            ram: [0; 256],
+            sp: RAM_SIZE,
+            flags: Flags::default(),
+            arithmetic: Arithmetic::default(),
+            interrupts: InterruptController::new(),
+            vectors: None,
+            halted: false,
+            executed: 0,
+            cycles: 0,
+            timing: Timing::default(),
+            bus,
+            tracer: None,
+            writes: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            hit: None,
+            stopped_at: None,
         }
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
-        loop {
-            self.fetch()?;
+    pub fn pc(&self) -> Addr {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: Addr) {
+        self.pc = pc;
+    }
+
+    pub fn sp(&self) -> Addr {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: Addr) {
+        self.sp = sp;
+    }
+
+    pub fn register(&self, slot: Slot) -> u16 {
+        self.register.read(slot)
+    }
+
+    pub fn set_register(&mut self, slot: Slot, data: u16) {
+        self.register.write(slot, data);
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.flags = flags;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
 
-            match self.decode()? {
-                Opcode::Hlt => break Ok(()),
-                code => self.execute(code)?,
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    /// Lets devices and the host raise lines or change the mask.
+    pub fn interrupts_mut(&mut self) -> &mut InterruptController {
+        &mut self.interrupts
+    }
+
+    pub fn vectors(&self) -> Option<Addr> {
+        self.vectors
+    }
+
+    /// Places the vector table at RAM address `base`: `TRAP_VECTOR` then one
+    /// entry per line, each holding the ROM address of its handler.
+    ///
+    /// Without a table, interrupts stay pending and faults stop the run.
+    pub fn set_vectors(&mut self, base: Option<Addr>) -> Result<(), EmuError> {
+        if let Some(base) = base {
+            match base.checked_add(LINES + 1) {
+                Some(end) if end <= RAM_SIZE => {}
+                end => {
+                    let addr = end.map_or(base, |end| end - 1);
+                    return Err(EmuError::RamOutOfBounds { addr });
+                }
             }
         }
+        self.vectors = base;
+        Ok(())
     }
 
-    fn fetch(&mut self) -> Result<(), String> {
-        self.ir.write(self.rom.read(self.pc)?);
-        self.pc += 1;
-        Ok(())
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    fn decode(&self) -> Result<Opcode, String> {
-        use Opcode::*;
+    /// Number of instructions executed so far.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Cycles elapsed so far under the current `Timing`.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Charges instructions according to `timing` from now on; devices are
+    /// clocked by the same cycles.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// Reports every executed instruction to `tracer` from now on.
+    pub fn set_tracer<T: Tracer + 'static>(&mut self, tracer: T) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+
+    /// Stops the run loop before the instruction at `addr` executes. Returns
+    /// `false` if it was already set.
+    ///
+    /// Resuming from a stop at `addr` steps over it; see `run_until`.
+    pub fn add_breakpoint(&mut self, addr: Addr) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: Addr) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = Addr> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Returns `false` if `watchpoint` was already set.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) {
+            return false;
+        }
+        self.watchpoints.push(watchpoint);
+        true
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|&w| w != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    /// Where devices get attached.
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn run(&mut self) -> Result<RunStats, EmuError> {
+        let (executed, cycles) = (self.executed, self.cycles);
+        match self.run_until(|_| false) {
+            StopReason::Fault(err) => Err(err),
+            _ => Ok(RunStats {
+                instructions: self.executed - executed,
+                cycles: self.cycles - cycles,
+            }),
+        }
+    }
+
+    /// Executes a single instruction, or enters the handler of a pending
+    /// interrupt without running any of it.
+    pub fn step(&mut self) -> StopReason {
+        self.run_for(1)
+    }
+
+    /// Executes one instruction like `step`, except that a `call` runs until it
+    /// returns to the instruction after it with `sp` back where it was.
+    ///
+    /// `None` if the call has not returned after `budget` instructions.
+    pub fn step_over(&mut self, budget: usize) -> Option<StopReason> {
+        let call = self
+            .bus
+            .fetch(self.pc)
+            .and_then(Opcode::decode)
+            .is_ok_and(|code| matches!(code, Opcode::Call(_)));
+        if !call {
+            return Some(self.step());
+        }
+        let (after, depth) = (self.pc + 1, self.sp);
+        let mut left = budget;
+        let reason = self.run_until(|cpu| {
+            left -= 1;
+            (cpu.pc == after && cpu.sp == depth) || left == 0
+        });
+        match reason {
+            StopReason::Breakpoint(pc) if pc == after && self.sp == depth => {
+                Some(StopReason::BudgetExhausted)
+            }
+            StopReason::Breakpoint(_) if left == 0 && !self.breakpoints.contains(&self.pc) => None,
+            reason => Some(reason),
+        }
+    }
+
+    /// Executes at most `budget` instructions.
+    pub fn run_for(&mut self, budget: usize) -> StopReason {
+        self.run_with(Some(budget), |_| false)
+    }
+
+    /// Executes until `predicate` holds after an instruction, the CPU halts or faults.
+    ///
+    /// Breakpoints are checked before each instruction, including the first,
+    /// except at the pc where the previous run stopped, so resuming from a stop
+    /// always makes progress. The predicate is not consulted before the first
+    /// instruction.
+    pub fn run_until<F>(&mut self, predicate: F) -> StopReason
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        self.run_with(None, predicate)
+    }
+
+    fn run_with<F>(&mut self, budget: Option<usize>, mut predicate: F) -> StopReason
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        let mut resuming = self.stopped_at.take() == Some(self.pc);
+        let mut executed = 0;
+        let reason = loop {
+            if self.halted {
+                break StopReason::Halted;
+            }
+            if !resuming && self.breakpoints.contains(&self.pc) {
+                break StopReason::Breakpoint(self.pc);
+            }
+            resuming = false;
+            if budget.is_some_and(|budget| executed >= budget) {
+                break StopReason::BudgetExhausted;
+            }
+            if let Err(err) = self.cycle() {
+                self.hit = None;
+                break StopReason::Fault(err);
+            }
+            executed += 1;
 
-        let code = match self.ir.code() {
-            0b0000 => Mov(self.ir.reg_a(), self.ir.reg_b()),
-            0b0001 => Add(self.ir.reg_a(), self.ir.reg_b()),
-            0b0010 => Sub(self.ir.reg_a(), self.ir.reg_b()),
-            0b0011 => And(self.ir.reg_a(), self.ir.reg_b()),
-            0b0100 => Or(self.ir.reg_a(), self.ir.reg_b()),
-            0b0101 => Sl(self.ir.reg_a()),
-            0b0110 => Sr(self.ir.reg_a()),
-            0b0111 => Sra(self.ir.reg_a()),
-            0b1000 => Ldl(self.ir.reg_a(), self.ir.data()),
-            0b1001 => Ldh(self.ir.reg_a(), self.ir.data()),
-            0b1010 => Cmp(self.ir.reg_a(), self.ir.reg_b()),
-            0b1011 => Je(self.ir.addr()),
-            0b1100 => Jmp(self.ir.addr()),
-            0b1101 => Ld(self.ir.reg_a(), self.ir.addr()),
-            0b1110 => St(self.ir.reg_a(), self.ir.addr()),
-            0b1111 => Hlt,
-            _ => return Err("unknown operation code".to_string()),
+            if let Some(hit) = self.hit.take() {
+                break StopReason::Watchpoint(hit);
+            }
+            if !self.halted && predicate(self) {
+                break StopReason::Breakpoint(self.pc);
+            }
         };
+        self.stopped_at = Some(self.pc);
+        reason
+    }
+
+    /// Enters the handler of the highest-priority interrupt that may be taken
+    /// now, without executing anything; returns whether it did.
+    ///
+    /// Every step does this first. It is public for models such as `Pipeline`
+    /// that treat interrupt entry as a redirect of its own.
+    pub fn interrupt(&mut self) -> Result<bool, EmuError> {
+        let base = match self.vectors {
+            Some(base) => base,
+            None => return Ok(false),
+        };
+        let line = match self.interrupts.acknowledge() {
+            Some(line) => line,
+            None => return Ok(false),
+        };
+        self.enter(base, line + 1, None)?;
+        self.elapse(self.timing.interrupt);
+        Ok(true)
+    }
 
+    /// Executes one instruction, or only enters an interrupt handler so that
+    /// the next cycle checks breakpoints at its first instruction.
+    fn cycle(&mut self) -> Result<(), EmuError> {
+        if self.interrupt()? {
+            return Ok(());
+        }
+
+        let mut cycles = 0;
+        let (pc, flags) = (self.pc, self.flags);
+        match self.instruction() {
+            Ok(code) => cycles += self.timing.cycles(&code, self.pc != pc + 1),
+            Err(err) => match self.vectors {
+                Some(base) if self.interrupts.saved().is_none() => {
+                    self.pc = pc + 1;
+                    self.flags = flags;
+                    self.enter(base, TRAP_VECTOR, Some(err))?;
+                    cycles += self.timing.interrupt;
+                }
+                _ => return Err(err),
+            },
+        }
+        self.elapse(cycles);
+        Ok(())
+    }
+
+    /// Advances the clock and the devices, latching the lines they raise.
+    ///
+    /// Steps do this by their `timing`. It is public for models such as
+    /// `Pipeline` that keep the clock themselves under `Timing::zero`.
+    pub fn elapse(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        let lines = self.bus.tick(cycles);
+        self.interrupts.raise_all(lines);
+    }
+
+    /// Saves the state `reti` restores and jumps through `vector`.
+    fn enter(
+        &mut self,
+        base: Addr,
+        vector: usize,
+        cause: Option<EmuError>,
+    ) -> Result<(), EmuError> {
+        let handler = self.bus.read(base + vector)?;
+        self.interrupts.enter(Saved {
+            pc: self.pc,
+            flags: self.flags,
+            enabled: self.interrupts.is_enabled(),
+            cause,
+        });
+        self.pc = handler as Addr;
+        Ok(())
+    }
+
+    fn instruction(&mut self) -> Result<Opcode, EmuError> {
+        let pc = self.pc;
+        let before = self.register.all();
+        self.fetch()?;
+
+        let code = self.decode()?;
+        match code {
+            Opcode::Hlt => self.halted = true,
+            code => self.execute(code)?,
+        }
+        self.executed += 1;
+
+        if !self.watchpoints.is_empty() {
+            self.watch_registers(pc, &before);
+        }
+
+        if self.tracer.is_some() {
+            let registers = self.register.all();
+            let event = TraceEvent {
+                step: self.executed,
+                pc,
+                word: self.ir.read(),
+                code,
+                registers,
+                changed: TraceEvent::diff(&before, &registers),
+                flags: self.flags,
+                writes: mem::take(&mut self.writes),
+                next_pc: self.pc,
+            };
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&event);
+            }
+        }
         Ok(code)
     }
 
-    fn execute(&mut self, code: Opcode) -> Result<(), String> {
+    fn fetch(&mut self) -> Result<(), EmuError> {
+        self.ir.write(self.bus.fetch(self.pc)?);
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn decode(&self) -> Result<Opcode, EmuError> {
+        self.ir.decode()
+    }
+
+    fn execute(&mut self, code: Opcode) -> Result<(), EmuError> {
         use opcode::Opcode::*;
 
         match code {
             Mov(reg_a, reg_b) => self.register.write(reg_a, self.register.read(reg_b)),
             Add(reg_a, reg_b) => {
-                let data = self.register.read(reg_a) + self.register.read(reg_b);
-                self.register.write(reg_a, data)
+                let (lhs, rhs) = (self.register.read(reg_a), self.register.read(reg_b));
+                let (data, flags) = Flags::add(lhs, rhs);
+                self.trap(flags.carry, code, lhs, rhs)?;
+                self.register.write(reg_a, data);
+                self.flags = flags;
             }
             Sub(reg_a, reg_b) => {
-                let data = self.register.read(reg_a) - self.register.read(reg_b);
-                self.register.write(reg_a, data)
+                let (lhs, rhs) = (self.register.read(reg_a), self.register.read(reg_b));
+                let (data, flags) = Flags::sub(lhs, rhs);
+                self.trap(flags.carry, code, lhs, rhs)?;
+                self.register.write(reg_a, data);
+                self.flags = flags;
             }
             And(reg_a, reg_b) => {
                 let data = self.register.read(reg_a) & self.register.read(reg_b);
-                self.register.write(reg_a, data)
+                self.register.write(reg_a, data);
+                self.flags = Flags::of(data);
             }
             Or(reg_a, reg_b) => {
                 let data = self.register.read(reg_a) | self.register.read(reg_b);
-                self.register.write(reg_a, data)
+                self.register.write(reg_a, data);
+                self.flags = Flags::of(data);
+            }
+            Sl(reg_a) => {
+                let data = self.register.read(reg_a);
+                self.trap(data & 0x8000 != 0, code, data, 1)?;
+                self.register.write(reg_a, data << 1);
+                self.flags = Flags::shift(data << 1, data & 0x8000 != 0);
+            }
+            Sr(reg_a) => {
+                let data = self.register.read(reg_a);
+                self.register.write(reg_a, data >> 1);
+                self.flags = Flags::shift(data >> 1, data & 1 != 0);
             }
-            Sl(reg_a) => self.register.write(reg_a, self.register.read(reg_a) << 1),
-            Sr(reg_a) => self.register.write(reg_a, self.register.read(reg_a) >> 1),
             Sra(reg_a) => {
-                let data1 = self.register.read(reg_a) & 0b1000_0000_0000_0000;
-                let data2 = self.register.read(reg_a) >> 1;
-                self.register.write(reg_a, data1 | data2)
+                let data = self.register.read(reg_a);
+                let shifted = data & 0b1000_0000_0000_0000 | data >> 1;
+                self.register.write(reg_a, shifted);
+                self.flags = Flags::shift(shifted, data & 1 != 0);
             }
             Ldl(reg_a, data) => {
                 let high = self.register.read(reg_a) & 0xff00;
@@ -114,20 +596,131 @@ impl CpuEmu {
                 let low = self.register.read(reg_a) & 0x00ff;
                 self.register.write(reg_a, high | low)
             }
-            Cmp(reg_a, reg_b) => self.flag = self.register.read(reg_a) == self.register.read(reg_b),
-            Je(addr) => {
-                if self.flag {
-                    self.pc = addr
-                }
+            Cmp(reg_a, reg_b) => {
+                self.flags = Flags::sub(self.register.read(reg_a), self.register.read(reg_b)).1
             }
+            Je(addr) if self.flags.zero => self.pc = addr,
+            Jne(addr) if !self.flags.zero => self.pc = addr,
+            Jlt(addr) if self.flags.less() => self.pc = addr,
+            Jge(addr) if !self.flags.less() => self.pc = addr,
+            Jc(addr) if self.flags.carry => self.pc = addr,
+            Jnc(addr) if !self.flags.carry => self.pc = addr,
             Jmp(addr) => self.pc = addr,
-            Ld(reg_a, addr) => self.register.write(reg_a, self.ram[addr]),
-            St(reg_a, addr) => self.ram[addr] = self.register.read(reg_a),
+            Ld(reg_a, addr) => {
+                let data = self.load(addr)?;
+                self.register.write(reg_a, data)
+            }
+            St(reg_a, addr) => self.store(addr, self.register.read(reg_a))?,
+            Push(reg_a) => self.push(self.register.read(reg_a))?,
+            Pop(reg_a) => {
+                let data = self.pop()?;
+                self.register.write(reg_a, data)
+            }
+            Call(addr) => {
+                self.push(self.pc as u16)?;
+                self.pc = addr;
+            }
+            Ret => self.pc = self.pop()? as Addr,
+            LdInd(reg_a, reg_b) => {
+                let data = self.load(self.register.read(reg_b) as Addr)?;
+                self.register.write(reg_a, data)
+            }
+            StInd(reg_a, reg_b) => {
+                let addr = self.register.read(reg_b) as Addr;
+                self.store(addr, self.register.read(reg_a))?
+            }
+            JmpInd(reg_b) => self.pc = self.register.read(reg_b) as Addr,
+            Ei => self.interrupts.set_enabled(true),
+            Di => self.interrupts.set_enabled(false),
+            Reti => {
+                let saved = self
+                    .interrupts
+                    .leave()
+                    .ok_or(EmuError::IllegalInstruction {
+                        word: self.ir.read(),
+                    })?;
+                self.pc = saved.pc;
+                self.flags = saved.flags;
+            }
             _ => {}
         }
 
         Ok(())
     }
+
+    /// Faults the instruction just fetched, i.e. the one at `pc - 1`, if it
+    /// `overflowed` and the machine traps on overflow.
+    fn trap(&self, overflowed: bool, code: Opcode, lhs: u16, rhs: u16) -> Result<(), EmuError> {
+        if overflowed && self.arithmetic == Arithmetic::Trapping {
+            return Err(EmuError::ArithmeticOverflow {
+                pc: self.pc - 1,
+                code,
+                lhs,
+                rhs,
+            });
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, data: u16) -> Result<(), EmuError> {
+        if self.sp == 0 || self.sp > RAM_SIZE {
+            return Err(EmuError::StackOverflow { pc: self.pc - 1 });
+        }
+        self.sp -= 1;
+        self.store(self.sp, data)
+    }
+
+    fn pop(&mut self) -> Result<u16, EmuError> {
+        if self.sp >= RAM_SIZE {
+            return Err(EmuError::StackUnderflow { pc: self.pc - 1 });
+        }
+        let data = self.load(self.sp)?;
+        self.sp += 1;
+        Ok(data)
+    }
+
+    fn load(&mut self, addr: Addr) -> Result<u16, EmuError> {
+        let data = self.bus.read(addr)?;
+        if !self.watchpoints.is_empty() {
+            self.watch(Watchpoint::Read(addr), self.pc - 1, Some(data), data);
+        }
+        Ok(data)
+    }
+
+    fn store(&mut self, addr: Addr, data: u16) -> Result<(), EmuError> {
+        let old = self.bus.peek(addr);
+        self.bus.write(addr, data)?;
+        if !self.watchpoints.is_empty() {
+            self.watch(Watchpoint::Write(addr), self.pc - 1, old, data);
+        }
+        if self.tracer.is_some() {
+            self.writes.push((addr, data));
+        }
+        Ok(())
+    }
+
+    fn watch_registers(&mut self, pc: Addr, before: &[u16; 8]) {
+        for (slot, new) in TraceEvent::diff(before, &self.register.all()) {
+            self.watch(
+                Watchpoint::Register(slot),
+                pc,
+                Some(before[slot as usize]),
+                new,
+            );
+        }
+    }
+
+    /// Records the first hit of the current instruction for `run_with` to report.
+    fn watch(&mut self, watchpoint: Watchpoint, pc: Addr, old: Option<u16>, new: u16) {
+        if self.hit.is_none() && self.watchpoints.contains(&watchpoint) {
+            self.hit = Some(WatchHit {
+                watchpoint,
+                pc,
+                old,
+                new,
+            });
+        }
+    }
 }
 
 #[cfg(test)]
@@ -141,10 +734,8 @@ mod tests {
     #[test]
     fn test_halt() {
         if let Err(msg) = CpuEmu::new(Rom::new(vec![halt()])).run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
-
-        assert!(true);
     }
 
     #[test]
@@ -156,7 +747,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg0), 0);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 30)
@@ -173,7 +764,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg1), 15);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 20);
@@ -190,7 +781,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg0), 15);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 10);
@@ -206,7 +797,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg0), 0b0101);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 0b0100);
@@ -222,7 +813,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg0), 0b0101);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 0b0111);
@@ -237,7 +828,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg1), 0b0011);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg1), 0b0110);
@@ -252,7 +843,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg1), 0b1100);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg1), 0b0110);
@@ -269,7 +860,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg1), 0b1000_0000_1100_0001);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 0b0000_0000_0110_0000);
@@ -285,7 +876,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg0), 0b0110_0000_0000_0000);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 0b0110_0000_10110010);
@@ -300,7 +891,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg0), 0b00000000_00000101);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
 
         assert_eq!(cpu.register.read(Slot::Reg0), 0b10110010_00000101);
@@ -313,11 +904,11 @@ mod tests {
         cpu.register.write(Slot::Reg0, 5);
         cpu.register.write(Slot::Reg1, 5);
 
-        assert_eq!(cpu.flag, false);
+        assert!(!cpu.flags.zero);
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
-        assert_eq!(cpu.flag, true);
+        assert!(cpu.flags.zero);
     }
 
     #[test]
@@ -327,11 +918,11 @@ mod tests {
         cpu.register.write(Slot::Reg0, 5);
         cpu.register.write(Slot::Reg1, 6);
 
-        assert_eq!(cpu.flag, false);
+        assert!(!cpu.flags.zero);
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
-        assert_eq!(cpu.flag, false);
+        assert!(!cpu.flags.zero);
     }
 
     #[test]
@@ -350,7 +941,7 @@ mod tests {
         assert_eq!(cpu.register.read(Slot::Reg0), 10);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
         assert_eq!(cpu.register.read(Slot::Reg0), 10);
     }
@@ -366,11 +957,11 @@ mod tests {
         let mut cpu = CpuEmu::new(rom);
         cpu.register.write(Slot::Reg0, 10);
 
-        assert_eq!(cpu.flag, false);
+        assert!(!cpu.flags.zero);
         assert_eq!(cpu.register.read(Slot::Reg0), 10);
 
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
         assert_eq!(cpu.register.read(Slot::Reg0), 10);
     }
@@ -379,12 +970,12 @@ mod tests {
     fn test_run_ld() {
         let rom = Rom::new(vec![0b1101_000_00000111, halt()]);
         let mut cpu = CpuEmu::new(rom);
-        cpu.ram[7] = 100;
+        cpu.write_ram(7, &[100]).unwrap();
 
         assert_eq!(cpu.register.read(Slot::Reg0), 0);
-        assert_eq!(cpu.ram[7], 100);
+        assert_eq!(cpu.ram(7..8), Ok(&[100][..]));
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
         assert_eq!(cpu.register.read(Slot::Reg0), 100);
     }
@@ -396,10 +987,690 @@ mod tests {
         cpu.register.write(Slot::Reg0, 50);
 
         assert_eq!(cpu.register.read(Slot::Reg0), 50);
-        assert_eq!(cpu.ram[7], 0);
+        assert_eq!(cpu.ram(7..8), Ok(&[0][..]));
+        if let Err(msg) = cpu.run() {
+            panic!("{}", msg);
+        }
+        assert_eq!(cpu.ram(7..8), Ok(&[50][..]));
+    }
+
+    #[test]
+    fn test_run_past_end_of_rom() {
+        let mut cpu = CpuEmu::new(Rom::new(vec![0b0000_000_001_00000]));
+        assert_eq!(cpu.run(), Err(EmuError::RomOutOfBounds { pc: 1 }));
+    }
+
+    #[test]
+    fn test_run_illegal_instruction() {
+        let mut cpu = CpuEmu::new(Rom::new(vec![0xf800, halt()]));
+        assert_eq!(
+            cpu.run(),
+            Err(EmuError::IllegalInstruction { word: 0xf800 })
+        );
+    }
+
+    #[test]
+    fn test_run_sub_borrow() {
+        let rom = Rom::new(vec![0b0000_010_010_00000, 0b0010_000_001_00000, halt()]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.register.write(Slot::Reg1, 1);
+
+        if let Err(msg) = cpu.run() {
+            panic!("{}", msg);
+        }
+        assert_eq!(cpu.register.read(Slot::Reg0), 0xffff);
+        assert_eq!(cpu.flags.to_string(), "-CN-");
+    }
+
+    #[test]
+    fn test_trapping_arithmetic() {
+        let rom = Rom::new(vec![0b0000_010_010_00000, 0b0010_000_001_00000, halt()]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.set_arithmetic(Arithmetic::Trapping);
+        cpu.register.write(Slot::Reg1, 1);
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmuError::ArithmeticOverflow {
+                pc: 1,
+                code: Opcode::Sub(Slot::Reg0, Slot::Reg1),
+                lhs: 0,
+                rhs: 1,
+            })
+        );
+        assert_eq!(cpu.register.read(Slot::Reg0), 0);
+        assert_eq!(cpu.flags, Flags::default());
+
+        let rom = Rom::new(vec![0b0001_000_001_00000, 0b0101_000_000_00000, halt()]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.set_arithmetic(Arithmetic::Trapping);
+        cpu.register.write(Slot::Reg0, 0x4000);
+        cpu.register.write(Slot::Reg1, 0x4000);
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmuError::ArithmeticOverflow {
+                pc: 1,
+                code: Opcode::Sl(Slot::Reg0),
+                lhs: 0x8000,
+                rhs: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_run_add_carry() {
+        // Adds 0x0001_ffff + 0x0000_0001 as two-word numbers in r1:r0 and r3:r2.
+        let rom = Rom::new(vec![
+            0b0001_000_010_00000, // add r0, r2
+            0b10100_000_00000011, // jnc 3
+            0b0001_001_100_00000, // add r1, r4 (r4 = 1, the carry)
+            0b0001_001_011_00000, // add r1, r3
+            halt(),
+        ]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.register.write(Slot::Reg0, 0xffff);
+        cpu.register.write(Slot::Reg1, 0x0001);
+        cpu.register.write(Slot::Reg2, 0x0001);
+        cpu.register.write(Slot::Reg4, 0x0001);
+
         if let Err(msg) = cpu.run() {
-            panic!(msg);
+            panic!("{}", msg);
         }
-        assert_eq!(cpu.ram[7], 50);
+        assert_eq!(cpu.register.read(Slot::Reg0), 0);
+        assert_eq!(cpu.register.read(Slot::Reg1), 2);
+    }
+
+    #[test]
+    fn test_conditional_jumps() {
+        // cmp r0, r1, then the jump under test to 3; r2 = 1 if it fell through.
+        let taken = |jump: u16, lhs: u16, rhs: u16| {
+            let rom = Rom::new(vec![
+                0b1010_000_001_00000,
+                jump | 3,
+                0b1000_010_00000001, // ldl r2, 1
+                halt(),
+            ]);
+            let mut cpu = CpuEmu::new(rom);
+            cpu.register.write(Slot::Reg0, lhs);
+            cpu.register.write(Slot::Reg1, rhs);
+            cpu.run().unwrap();
+            cpu.register.read(Slot::Reg2) == 0
+        };
+        let je = Opcode::Je(0).encode();
+        let jne = Opcode::Jne(0).encode();
+        let jlt = Opcode::Jlt(0).encode();
+        let jge = Opcode::Jge(0).encode();
+        let jc = Opcode::Jc(0).encode();
+        let jnc = Opcode::Jnc(0).encode();
+        let minus_one = 0xffff;
+
+        assert!(taken(je, 4, 4) && !taken(je, 4, 5));
+        assert!(taken(jne, 4, 5) && !taken(jne, 4, 4));
+        assert!(taken(jlt, minus_one, 1) && !taken(jlt, 1, minus_one) && !taken(jlt, 4, 4));
+        assert!(taken(jge, 1, minus_one) && taken(jge, 4, 4) && !taken(jge, minus_one, 1));
+        assert!(taken(jc, 1, minus_one) && !taken(jc, minus_one, 1) && !taken(jc, 4, 4));
+        assert!(taken(jnc, minus_one, 1) && taken(jnc, 4, 4) && !taken(jnc, 1, 2));
+    }
+
+    #[test]
+    fn test_cycles() {
+        use Opcode::*;
+        // ld (2), cmp (1), je not taken (1), jne taken (2), jmp (2), hlt (1)
+        let rom = Rom::new(
+            [
+                Ld(Slot::Reg0, 0),
+                Cmp(Slot::Reg0, Slot::Reg1),
+                Je(5),
+                Jne(5),
+                Hlt,
+                Jmp(6),
+                Hlt,
+            ]
+            .iter()
+            .map(Opcode::encode)
+            .collect(),
+        );
+        let mut cpu = CpuEmu::new(rom);
+        cpu.write_ram(0, &[5]).unwrap();
+
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(cpu.cycles(), 2);
+        assert_eq!(
+            cpu.run(),
+            Ok(RunStats {
+                instructions: 5,
+                cycles: 7
+            })
+        );
+        assert_eq!(cpu.snapshot().cycles, 9);
+
+        let mut cpu = CpuEmu::new(Rom::new(vec![halt()]));
+        let mut timing = Timing::uniform();
+        timing.set(Spec::by_mnemonic("hlt").unwrap(), Cost::new(4, 0));
+        cpu.set_timing(timing);
+        assert_eq!(cpu.run().map(|stats| stats.cycles), Ok(4));
+    }
+
+    #[test]
+    fn test_call_ret() {
+        use Opcode::*;
+        let rom = Rom::new(
+            [
+                Push(Slot::Reg0),
+                Call(5),
+                Pop(Slot::Reg1),
+                Hlt,
+                Hlt,
+                Ldl(Slot::Reg0, 7), // 5: clobbers r0, which the caller saved
+                Ret,
+            ]
+            .iter()
+            .map(Opcode::encode)
+            .collect(),
+        );
+        let mut cpu = CpuEmu::new(rom);
+        cpu.register.write(Slot::Reg0, 3);
+
+        assert_eq!(cpu.run_until(|cpu| cpu.pc == 6), StopReason::Breakpoint(6));
+        assert_eq!(cpu.sp, RAM_SIZE - 2);
+        assert_eq!(cpu.ram(RAM_SIZE - 2..RAM_SIZE), Ok(&[2, 3][..]));
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.pc, 4);
+        assert_eq!(cpu.sp, RAM_SIZE);
+        assert_eq!(cpu.register.read(Slot::Reg0), 7);
+        assert_eq!(cpu.register.read(Slot::Reg1), 3);
+    }
+
+    #[test]
+    fn test_indirect() {
+        use Opcode::*;
+        // Copies ram[0..3] to ram[8..11], then jumps through r6 to the hlt at 14.
+        let rom = Rom::new(
+            [
+                Ldl(Slot::Reg1, 0),
+                Ldl(Slot::Reg2, 8),
+                Ldl(Slot::Reg3, 11),
+                Ldl(Slot::Reg4, 1),
+                Ldl(Slot::Reg6, 14),
+                LdInd(Slot::Reg0, Slot::Reg1), // 5
+                StInd(Slot::Reg0, Slot::Reg2),
+                Add(Slot::Reg1, Slot::Reg4),
+                Add(Slot::Reg2, Slot::Reg4),
+                Cmp(Slot::Reg2, Slot::Reg3),
+                Jne(5),
+                JmpInd(Slot::Reg6),
+                Hlt,
+                Hlt,
+                Hlt, // 14
+            ]
+            .iter()
+            .map(Opcode::encode)
+            .collect(),
+        );
+        let mut cpu = CpuEmu::new(rom);
+        cpu.write_ram(0, &[4, 5, 6]).unwrap();
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.ram(8..11), Ok(&[4, 5, 6][..]));
+        assert_eq!(cpu.pc, 15);
+    }
+
+    #[test]
+    fn test_custom_bus() {
+        /// One flat memory with the program at `ROM_BASE`, and no `peek`.
+        struct Flat(Vec<u16>);
+
+        impl Bus for Flat {
+            fn read(&mut self, addr: Addr) -> Result<u16, EmuError> {
+                self.0
+                    .get(addr)
+                    .copied()
+                    .ok_or(EmuError::RamOutOfBounds { addr })
+            }
+
+            fn write(&mut self, addr: Addr, data: u16) -> Result<(), EmuError> {
+                *self
+                    .0
+                    .get_mut(addr)
+                    .ok_or(EmuError::RamOutOfBounds { addr })? = data;
+                Ok(())
+            }
+        }
+
+        let mut memory = vec![0; 1 << 16];
+        let program = [
+            Opcode::Ldl(Slot::Reg0, 7),
+            Opcode::St(Slot::Reg0, 64),
+            Opcode::Hlt,
+        ];
+        for (offset, code) in program.iter().enumerate() {
+            memory[ROM_BASE + offset] = code.encode();
+        }
+        let mut cpu = CpuEmu::with_bus(Flat(memory));
+        cpu.add_watchpoint(Watchpoint::Write(64));
+
+        let hit = WatchHit {
+            watchpoint: Watchpoint::Write(64),
+            pc: 1,
+            old: None,
+            new: 7,
+        };
+        assert_eq!(cpu.run_for(10), StopReason::Watchpoint(hit));
+        assert_eq!(hit.to_string(), "watchpoint write ram[64] at pc 1: 7");
+        assert!(cpu.run().is_ok());
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.bus().0[64], 7);
+    }
+
+    #[test]
+    fn test_bus() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        /// Counts the writes it receives.
+        #[derive(Default)]
+        struct Counter(u16);
+
+        impl Device for Counter {
+            fn read(&mut self, _offset: Addr) -> u16 {
+                self.0
+            }
+
+            fn write(&mut self, _offset: Addr, _data: u16) {
+                self.0 += 1;
+            }
+        }
+
+        use Opcode::*;
+        // Reads ROM word 4 through the data bus, then pokes the device twice.
+        let rom = Rom::new(
+            [
+                Ldh(Slot::Reg1, 0x80),
+                Ldl(Slot::Reg1, 4),
+                LdInd(Slot::Reg0, Slot::Reg1),
+                Ldh(Slot::Reg2, 0xff),
+                Hlt,
+                StInd(Slot::Reg0, Slot::Reg2),
+                StInd(Slot::Reg0, Slot::Reg1),
+            ]
+            .iter()
+            .map(Opcode::encode)
+            .collect(),
+        );
+        let mut cpu = CpuEmu::new(rom);
+        let counter = Rc::new(RefCell::new(Counter::default()));
+        cpu.bus_mut().attach(0xff00..0xff01, counter.clone());
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.register.read(Slot::Reg0), Opcode::Hlt.encode());
+
+        cpu.halted = false;
+        cpu.pc = 5;
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(counter.borrow().0, 1);
+        assert_eq!(
+            cpu.step(),
+            StopReason::Fault(EmuError::ReadOnly { addr: ROM_BASE + 4 })
+        );
+    }
+
+    #[test]
+    fn test_indirect_out_of_bounds() {
+        let rom = Rom::new(vec![Opcode::StInd(Slot::Reg0, Slot::Reg1).encode()]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.register.write(Slot::Reg1, 0x0100);
+
+        assert_eq!(cpu.run(), Err(EmuError::RamOutOfBounds { addr: 256 }));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        use Opcode::*;
+        let rom = Rom::new(
+            [
+                Ei,
+                Ldl(Slot::Reg1, 1),
+                Jmp(2),
+                Hlt,
+                Add(Slot::Reg2, Slot::Reg1), // 4: handler for line 3
+                Reti,
+            ]
+            .iter()
+            .map(Opcode::encode)
+            .collect(),
+        );
+        let mut cpu = CpuEmu::new(rom);
+        cpu.set_vectors(Some(0xe0)).unwrap();
+        cpu.write_ram(0xe0 + 1 + 3, &[4]).unwrap();
+        cpu.run_for(3);
+        assert_eq!(cpu.pc, 2);
+        cpu.interrupts_mut().raise(3).unwrap();
+
+        // Entering the handler is a step of its own.
+        cpu.run_for(1);
+        assert_eq!(cpu.pc, 4);
+        assert_eq!(cpu.register.read(Slot::Reg2), 0);
+        assert!(!cpu.interrupts.is_enabled());
+        assert_eq!(cpu.interrupts.saved().map(|saved| saved.pc), Some(2));
+
+        cpu.run_for(2);
+        assert_eq!(cpu.register.read(Slot::Reg2), 1);
+        assert_eq!(cpu.pc, 2);
+        assert!(cpu.interrupts.is_enabled());
+        assert_eq!(cpu.interrupts.saved(), None);
+        assert_eq!(cpu.interrupts.pending(), 0);
+    }
+
+    #[test]
+    fn test_trap_vector() {
+        let rom = Rom::new(vec![
+            0xf800,
+            halt(),
+            0b1000_011_00001001, // 2: ldl r3, 9
+            Opcode::Reti.encode(),
+        ]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.set_vectors(Some(0xe0)).unwrap();
+        cpu.write_ram(0xe0 + TRAP_VECTOR, &[2]).unwrap();
+
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(cpu.pc, 2);
+        let cause = cpu.interrupts.saved().and_then(|saved| saved.cause.clone());
+        assert_eq!(cause, Some(EmuError::IllegalInstruction { word: 0xf800 }));
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.register.read(Slot::Reg3), 9);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn test_faults_without_handler() {
+        // `reti` outside a handler is illegal; a fault inside the trap handler
+        // stops the run rather than re-entering it.
+        let reti = Opcode::Reti.encode();
+        let mut cpu = CpuEmu::new(Rom::new(vec![reti]));
+        assert_eq!(cpu.run(), Err(EmuError::IllegalInstruction { word: reti }));
+
+        let mut cpu = CpuEmu::new(Rom::new(vec![0xf800]));
+        cpu.set_vectors(Some(0)).unwrap();
+        assert_eq!(
+            cpu.run(),
+            Err(EmuError::IllegalInstruction { word: 0xf800 })
+        );
+        assert_eq!(
+            cpu.set_vectors(Some(250)),
+            Err(EmuError::RamOutOfBounds { addr: 258 })
+        );
+        assert_eq!(
+            cpu.set_vectors(Some(usize::MAX)),
+            Err(EmuError::RamOutOfBounds { addr: usize::MAX })
+        );
+        assert_eq!(cpu.vectors(), Some(0));
+    }
+
+    #[test]
+    fn test_stack_overflow_underflow() {
+        let push = Opcode::Push(Slot::Reg0).encode();
+        let mut cpu = CpuEmu::new(Rom::new(vec![push; RAM_SIZE + 1]));
+        assert_eq!(cpu.run(), Err(EmuError::StackOverflow { pc: RAM_SIZE }));
+        assert_eq!(cpu.sp, 0);
+
+        let ret = Opcode::Ret.encode();
+        let mut cpu = CpuEmu::new(Rom::new(vec![ret]));
+        assert_eq!(cpu.run(), Err(EmuError::StackUnderflow { pc: 0 }));
+        assert_eq!(cpu.pc, 1);
+    }
+
+    #[test]
+    fn test_shift_carry() {
+        let rom = Rom::new(vec![0b0101_000_000_00000, 0b0110_001_000_00000, halt()]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.register.write(Slot::Reg0, 0x8000);
+        cpu.register.write(Slot::Reg1, 0x0003);
+
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(cpu.flags.to_string(), "ZC--");
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(cpu.flags.to_string(), "-C--");
+        assert_eq!(cpu.register.read(Slot::Reg1), 1);
+    }
+
+    #[test]
+    fn test_step() {
+        let rom = Rom::new(vec![0b1000_000_00000101, 0b0101_000_000_00000, halt()]);
+        let mut cpu = CpuEmu::new(rom);
+
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(cpu.register.read(Slot::Reg0), 5);
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(cpu.register.read(Slot::Reg0), 10);
+        assert_eq!(cpu.step(), StopReason::Halted);
+        assert_eq!(cpu.step(), StopReason::Halted);
+        assert_eq!(cpu.pc, 3);
+    }
+
+    #[test]
+    fn test_run_for() {
+        let rom = Rom::new(vec![0b1100_000_00000000]); // jmp 0
+        let mut cpu = CpuEmu::new(rom);
+
+        assert_eq!(cpu.run_for(0), StopReason::BudgetExhausted);
+        assert_eq!(cpu.run_for(1000), StopReason::BudgetExhausted);
+        assert_eq!(cpu.pc, 0);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let instructions = vec![
+            0b1000_001_00000001,  // ldl reg1, 1
+            0b0001_000_001_00000, // add reg0, reg1
+            0b1100_000_00000001,  // jmp 1
+        ];
+        let mut cpu = CpuEmu::new(Rom::new(instructions));
+
+        let reason = cpu.run_until(|cpu| cpu.register.read(Slot::Reg0) == 3);
+        assert_eq!(reason, StopReason::Breakpoint(2));
+
+        let reason = cpu.run_until(|cpu| cpu.pc == 2);
+        assert_eq!(reason, StopReason::Breakpoint(2));
+        assert_eq!(cpu.register.read(Slot::Reg0), 4);
+    }
+
+    #[test]
+    fn test_run_until_fault() {
+        let mut cpu = CpuEmu::new(Rom::new(vec![0b0000_000_000_00000]));
+        assert_eq!(
+            cpu.run_until(|_| false),
+            StopReason::Fault(EmuError::RomOutOfBounds { pc: 1 })
+        );
+    }
+
+    #[test]
+    fn test_state_api() {
+        let rom = Rom::new(vec![0b1101_000_01000000, 0b1110_000_01000001, halt()]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.write_ram(64, &[7]).unwrap();
+        cpu.set_register(Slot::Reg3, 3);
+        cpu.set_flags(Flags::from_bits(0b0001));
+
+        if let Err(msg) = cpu.run() {
+            panic!("{}", msg);
+        }
+
+        assert_eq!(cpu.ram(64..66), Ok(&[7, 7][..]));
+        assert_eq!(cpu.register(Slot::Reg0), 7);
+        assert_eq!(cpu.pc(), 3);
+        assert!(cpu.flags().zero && cpu.is_halted());
+
+        let state = cpu.snapshot();
+        assert_eq!(state.register(Slot::Reg3), 3);
+        assert_eq!(state.ram[65], 7);
+    }
+
+    #[test]
+    fn test_ram_out_of_bounds() {
+        let mut cpu = CpuEmu::new(Rom::new(vec![halt()]));
+        assert_eq!(
+            cpu.write_ram(255, &[1, 2]),
+            Err(EmuError::RamOutOfBounds { addr: 256 })
+        );
+        assert_eq!(
+            cpu.ram(250..300),
+            Err(EmuError::RamOutOfBounds { addr: 299 })
+        );
+        assert_eq!(
+            cpu.ram(Range { start: 1, end: 0 }),
+            Err(EmuError::RamOutOfBounds { addr: 1 })
+        );
+        assert_eq!(cpu.write_ram(0, &[]), Ok(()));
+        assert_eq!(
+            cpu.write_ram(300, &[]),
+            Err(EmuError::RamOutOfBounds { addr: 300 })
+        );
+        assert_eq!(
+            cpu.write_ram(usize::MAX, &[1]),
+            Err(EmuError::RamOutOfBounds { addr: usize::MAX })
+        );
+    }
+
+    #[test]
+    fn test_tracer() {
+        let rom = Rom::new(vec![0b1000_011_00000111, 0b1110_011_01000000, halt()]);
+        let mut cpu = CpuEmu::new(rom);
+        let events = Collect::default();
+        cpu.set_tracer(events.clone());
+
+        if let Err(msg) = cpu.run() {
+            panic!("{}", msg);
+        }
+
+        let events = events.0.borrow();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].changed, vec![(Slot::Reg3, 7)]);
+        assert_eq!(events[1].code, Opcode::St(Slot::Reg3, 64));
+        assert_eq!(events[1].writes, vec![(64, 7)]);
+        assert_eq!((events[2].step, events[2].pc), (3, 2));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let instructions = vec![
+            0b1000_001_00000001,  // ldl reg1, 1
+            0b0001_000_001_00000, // add reg0, reg1
+            0b1100_000_00000001,  // jmp 1
+        ];
+        let mut cpu = CpuEmu::new(Rom::new(instructions));
+        assert!(cpu.add_breakpoint(1));
+        assert!(!cpu.add_breakpoint(1));
+
+        assert_eq!(cpu.run_for(100), StopReason::Breakpoint(1));
+        assert_eq!(cpu.register(Slot::Reg0), 0);
+        assert_eq!(cpu.run_for(100), StopReason::Breakpoint(1));
+        assert_eq!(cpu.register(Slot::Reg0), 1);
+
+        assert!(cpu.remove_breakpoint(1));
+        assert_eq!(cpu.breakpoints().count(), 0);
+        assert_eq!(cpu.run_for(100), StopReason::BudgetExhausted);
+    }
+
+    #[test]
+    fn test_breakpoint_on_entry() {
+        let mut cpu = CpuEmu::new(Rom::new(vec![
+            0b1000_001_00000001, // ldl reg1, 1
+            0b1100_000_00000000, // jmp 0
+        ]));
+        cpu.add_breakpoint(0);
+        assert_eq!(cpu.run_for(100), StopReason::Breakpoint(0));
+        assert_eq!(cpu.executed(), 0);
+        assert_eq!(cpu.run_for(100), StopReason::Breakpoint(0));
+        assert_eq!(cpu.executed(), 2);
+
+        // Moving the pc onto a breakpoint is not a resume.
+        cpu.step();
+        cpu.set_pc(0);
+        assert_eq!(cpu.step(), StopReason::Breakpoint(0));
+    }
+
+    #[test]
+    fn test_breakpoint_on_handler() {
+        use Opcode::*;
+        let rom = Rom::new(
+            [
+                Ei,
+                Jmp(1),
+                Ldl(Slot::Reg1, 1), // 2: handler for line 0
+                Reti,
+            ]
+            .iter()
+            .map(Opcode::encode)
+            .collect(),
+        );
+        let mut cpu = CpuEmu::new(rom);
+        cpu.set_vectors(Some(0xe0)).unwrap();
+        cpu.write_ram(0xe0 + 1, &[2]).unwrap();
+        cpu.add_breakpoint(2);
+        cpu.run_for(2);
+        cpu.interrupts_mut().raise(0).unwrap();
+
+        assert_eq!(cpu.run_for(100), StopReason::Breakpoint(2));
+        assert_eq!(cpu.register(Slot::Reg1), 0);
+        assert!(cpu.interrupts().saved().is_some());
+        assert_eq!(cpu.step(), StopReason::BudgetExhausted);
+        assert_eq!(cpu.register(Slot::Reg1), 1);
+    }
+
+    #[test]
+    fn test_ram_watchpoints() {
+        let rom = Rom::new(vec![
+            0b1101_000_01000000, // ld reg0, 64
+            0b1110_000_01000001, // st reg0, 65
+            halt(),
+        ]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.write_ram(64, &[7]).unwrap();
+        cpu.add_watchpoint(Watchpoint::Read(64));
+        cpu.add_watchpoint(Watchpoint::Write(65));
+
+        let hit = WatchHit {
+            watchpoint: Watchpoint::Read(64),
+            pc: 0,
+            old: Some(7),
+            new: 7,
+        };
+        assert_eq!(cpu.run_for(100), StopReason::Watchpoint(hit));
+
+        let hit = WatchHit {
+            watchpoint: Watchpoint::Write(65),
+            pc: 1,
+            old: Some(0),
+            new: 7,
+        };
+        assert_eq!(cpu.run_for(100), StopReason::Watchpoint(hit));
+        assert_eq!(hit.to_string(), "watchpoint write ram[65] at pc 1: 0 -> 7");
+        assert_eq!(cpu.run_for(100), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_register_watchpoint() {
+        let rom = Rom::new(vec![
+            0b1000_001_00000000, // ldl reg1, 0 (no change)
+            0b1000_001_00000011, // ldl reg1, 3
+            halt(),
+        ]);
+        let mut cpu = CpuEmu::new(rom);
+        cpu.add_watchpoint(Watchpoint::Register(Slot::Reg1));
+        assert_eq!(
+            cpu.run_for(100),
+            StopReason::Watchpoint(WatchHit {
+                watchpoint: Watchpoint::Register(Slot::Reg1),
+                pc: 1,
+                old: Some(0),
+                new: 3,
+            })
+        );
+        assert!(cpu.remove_watchpoint(Watchpoint::Register(Slot::Reg1)));
+        assert!(cpu.watchpoints().is_empty());
     }
 }
@@ -0,0 +1,343 @@
+//! The data address space seen by `Ld`, `St` and the stack.
+//!
+//! | addresses                      | region                       |
+//! |--------------------------------|------------------------------|
+//! | `0 .. RAM_SIZE`                | RAM                          |
+//! | `ROM_BASE .. ROM_BASE + len`   | ROM, read-only               |
+//! | wherever they are attached     | `Device`s                    |
+//!
+//! Anything else is unmapped and faults with `EmuError::RamOutOfBounds`.
+//!
+//! That is the layout of `AddressDecoder`, the bus `CpuEmu::new` builds;
+//! `CpuEmu::with_bus` runs on any other `Bus`.
+
+use super::error::EmuError;
+use super::interrupt::LINES;
+use super::rom::Rom;
+use super::{Addr, RAM_SIZE};
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Data address of ROM word 0, so programs can read tables with `ld ra, [rb]`.
+pub const ROM_BASE: Addr = 0x8000;
+
+/// Memory as the CPU sees it.
+pub trait Bus {
+    fn read(&mut self, addr: Addr) -> Result<u16, EmuError>;
+
+    fn write(&mut self, addr: Addr, data: u16) -> Result<(), EmuError>;
+
+    /// Reads the instruction at `pc`, which counts words from the start of ROM.
+    fn fetch(&mut self, pc: Addr) -> Result<u16, EmuError> {
+        self.read(ROM_BASE + pc)
+            .map_err(|_| EmuError::RomOutOfBounds { pc })
+    }
+
+    /// Reads `addr` without side effects; `None` where that is not possible.
+    fn peek(&self, _addr: Addr) -> Option<u16> {
+        None
+    }
+
+    /// Advances attached devices by `cycles`; returns the interrupt lines they
+    /// raised, bit `n` for line `n`.
+    fn tick(&mut self, _cycles: u64) -> u8 {
+        0
+    }
+}
+
+/// A memory-mapped peripheral. Offsets count words from the start of its region.
+pub trait Device {
+    fn read(&mut self, offset: Addr) -> u16;
+
+    fn write(&mut self, offset: Addr, data: u16);
+
+    /// Advances the device by `cycles` of emulated time; returns an interrupt
+    /// line below `LINES` to raise, if any. Debug builds panic on any other
+    /// line; release builds drop it.
+    fn tick(&mut self, _cycles: u64) -> Option<usize> {
+        None
+    }
+}
+
+/// Lets the host keep a handle on a device after attaching it.
+impl<D: Device + ?Sized> Device for Rc<RefCell<D>> {
+    fn read(&mut self, offset: Addr) -> u16 {
+        self.borrow_mut().read(offset)
+    }
+
+    fn write(&mut self, offset: Addr, data: u16) {
+        self.borrow_mut().write(offset, data)
+    }
+
+    fn tick(&mut self, cycles: u64) -> Option<usize> {
+        self.borrow_mut().tick(cycles)
+    }
+}
+
+enum Target {
+    Ram,
+    Rom,
+    Device(Box<dyn Device>),
+}
+
+struct Region {
+    range: Range<Addr>,
+    target: Target,
+}
+
+/// Routes each data address to the RAM, the ROM or the device mapped there.
+pub struct AddressDecoder {
+    ram: [u16; RAM_SIZE],
+    rom: Rom,
+    regions: Vec<Region>,
+}
+
+impl AddressDecoder {
+    pub fn new(rom: Rom) -> Self {
+        let rom_end = ROM_BASE + rom.words().len();
+        Self {
+            ram: [0; RAM_SIZE],
+            rom,
+            regions: vec![
+                Region {
+                    range: 0..RAM_SIZE,
+                    target: Target::Ram,
+                },
+                Region {
+                    range: ROM_BASE..rom_end,
+                    target: Target::Rom,
+                },
+            ],
+        }
+    }
+
+    /// Maps `device` at `range`.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is empty or overlaps a region that is already mapped.
+    pub fn attach<D: Device + 'static>(&mut self, range: Range<Addr>, device: D) {
+        assert!(!range.is_empty(), "empty device region {:?}", range);
+        if let Some(region) = self
+            .regions
+            .iter()
+            .find(|region| region.range.start < range.end && range.start < region.range.end)
+        {
+            panic!("{:?} overlaps {:?}", range, region.range);
+        }
+        self.regions.push(Region {
+            range,
+            target: Target::Device(Box::new(device)),
+        });
+    }
+
+    /// The mapped regions in address order, named `ram`, `rom` or `device`.
+    pub fn regions(&self) -> Vec<(Range<Addr>, &'static str)> {
+        let mut regions: Vec<_> = self
+            .regions
+            .iter()
+            .map(|region| {
+                let name = match region.target {
+                    Target::Ram => "ram",
+                    Target::Rom => "rom",
+                    Target::Device(_) => "device",
+                };
+                (region.range.clone(), name)
+            })
+            .collect();
+        regions.sort_by_key(|(range, _)| range.start);
+        regions
+    }
+
+    pub fn ram(&self) -> &[u16; RAM_SIZE] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16; RAM_SIZE] {
+        &mut self.ram
+    }
+
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    pub fn rom_mut(&mut self) -> &mut Rom {
+        &mut self.rom
+    }
+
+    fn region(&self, addr: Addr) -> Option<&Region> {
+        self.regions
+            .iter()
+            .find(|region| region.range.contains(&addr))
+    }
+
+    fn region_mut(&mut self, addr: Addr) -> Result<&mut Region, EmuError> {
+        self.regions
+            .iter_mut()
+            .find(|region| region.range.contains(&addr))
+            .ok_or(EmuError::RamOutOfBounds { addr })
+    }
+}
+
+impl Bus for AddressDecoder {
+    fn read(&mut self, addr: Addr) -> Result<u16, EmuError> {
+        let region = self.region_mut(addr)?;
+        let offset = addr - region.range.start;
+        match &mut region.target {
+            Target::Ram => Ok(self.ram[offset]),
+            Target::Rom => self.rom.read(offset),
+            Target::Device(device) => Ok(device.read(offset)),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: u16) -> Result<(), EmuError> {
+        let region = self.region_mut(addr)?;
+        let offset = addr - region.range.start;
+        match &mut region.target {
+            Target::Ram => self.ram[offset] = data,
+            Target::Rom => return Err(EmuError::ReadOnly { addr }),
+            Target::Device(device) => device.write(offset, data),
+        }
+        Ok(())
+    }
+
+    fn fetch(&mut self, pc: Addr) -> Result<u16, EmuError> {
+        self.rom.read(pc)
+    }
+
+    fn tick(&mut self, cycles: u64) -> u8 {
+        let mut lines = 0;
+        for region in &mut self.regions {
+            if let Target::Device(device) = &mut region.target {
+                if let Some(line) = device.tick(cycles) {
+                    debug_assert!(line < LINES, "interrupt line {} out of range", line);
+                    if line < LINES {
+                        lines |= 1 << line;
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    /// `None` for devices and unmapped addresses.
+    fn peek(&self, addr: Addr) -> Option<u16> {
+        match self.region(addr)?.target {
+            Target::Ram => Some(self.ram[addr]),
+            Target::Rom => self.rom.read(addr - ROM_BASE).ok(),
+            Target::Device(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for AddressDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AddressDecoder")
+            .field("ram", &self.ram)
+            .field("rom", &self.rom)
+            .field("regions", &self.regions())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Remembers the last write and reads back its offset plus the value.
+    #[derive(Default)]
+    struct Latch {
+        value: u16,
+    }
+
+    impl Device for Latch {
+        fn read(&mut self, offset: Addr) -> u16 {
+            offset as u16 + self.value
+        }
+
+        fn write(&mut self, _offset: Addr, data: u16) {
+            self.value = data;
+        }
+    }
+
+    #[test]
+    fn test_routing() {
+        let mut bus = AddressDecoder::new(Rom::new(vec![0x1234, 0x5678]));
+        let latch = Rc::new(RefCell::new(Latch::default()));
+        bus.attach(0xff00..0xff04, latch.clone());
+
+        bus.write(3, 7).unwrap();
+        assert_eq!(bus.read(3), Ok(7));
+        assert_eq!(bus.read(ROM_BASE + 1), Ok(0x5678));
+        assert_eq!(bus.fetch(0), Ok(0x1234));
+
+        bus.write(0xff01, 40).unwrap();
+        assert_eq!(latch.borrow().value, 40);
+        assert_eq!(bus.read(0xff02), Ok(42));
+        assert_eq!(bus.peek(0xff02), None);
+        assert_eq!(bus.peek(3), Some(7));
+    }
+
+    #[test]
+    fn test_faults() {
+        let mut bus = AddressDecoder::new(Rom::new(vec![0x1234]));
+        assert_eq!(
+            bus.read(RAM_SIZE),
+            Err(EmuError::RamOutOfBounds { addr: RAM_SIZE })
+        );
+        assert_eq!(
+            bus.read(ROM_BASE + 1),
+            Err(EmuError::RamOutOfBounds { addr: ROM_BASE + 1 })
+        );
+        assert_eq!(
+            bus.write(ROM_BASE, 0),
+            Err(EmuError::ReadOnly { addr: ROM_BASE })
+        );
+        assert_eq!(bus.fetch(1), Err(EmuError::RomOutOfBounds { pc: 1 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps")]
+    fn test_overlapping_device() {
+        let mut bus = AddressDecoder::new(Rom::new(vec![]));
+        bus.attach(0xf0..0x110, Latch::default());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of range")]
+    fn test_tick_line_out_of_range() {
+        struct Stray;
+        impl Device for Stray {
+            fn read(&mut self, _offset: Addr) -> u16 {
+                0
+            }
+
+            fn write(&mut self, _offset: Addr, _data: u16) {}
+
+            fn tick(&mut self, _cycles: u64) -> Option<usize> {
+                Some(LINES)
+            }
+        }
+
+        let mut bus = AddressDecoder::new(Rom::new(vec![]));
+        bus.attach(0x1000..0x1001, Stray);
+        bus.tick(1);
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut bus = AddressDecoder::new(Rom::new(vec![0; 4]));
+        bus.attach(0x1000..0x1002, Latch::default());
+        assert_eq!(
+            bus.regions(),
+            vec![
+                (0..RAM_SIZE, "ram"),
+                (0x1000..0x1002, "device"),
+                (ROM_BASE..ROM_BASE + 4, "rom"),
+            ]
+        );
+    }
+}
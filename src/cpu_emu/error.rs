@@ -0,0 +1,59 @@
+use super::opcode::Opcode;
+use super::Addr;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    /// The PC points past the end of the ROM.
+    RomOutOfBounds { pc: Addr },
+    /// The fetched word does not decode to any instruction.
+    IllegalInstruction { word: u16 },
+    /// A data memory access to an address nothing is mapped at.
+    RamOutOfBounds { addr: Addr },
+    /// A store to the ROM's data window.
+    ReadOnly { addr: Addr },
+    /// A register index outside `r0..r7`.
+    InvalidRegister { index: u16 },
+    /// `Push` or `Call` with every RAM word already on the stack.
+    StackOverflow { pc: Addr },
+    /// `Pop` or `Ret` with an empty stack.
+    StackUnderflow { pc: Addr },
+    /// `Add`, `Sub` or `Sl` carried out of 16 bits under `Arithmetic::Trapping`;
+    /// `rhs` is the shift amount for `Sl`.
+    ArithmeticOverflow {
+        pc: Addr,
+        code: Opcode,
+        lhs: u16,
+        rhs: u16,
+    },
+    /// An interrupt line at or above `LINES` was raised or cleared.
+    InvalidInterruptLine { line: usize },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::RomOutOfBounds { pc } => write!(f, "pc {} is outside the ROM", pc),
+            EmuError::IllegalInstruction { word } => {
+                write!(f, "illegal instruction {:#06x}", word)
+            }
+            EmuError::RamOutOfBounds { addr } => {
+                write!(f, "address {} is outside the RAM", addr)
+            }
+            EmuError::ReadOnly { addr } => write!(f, "address {} is read-only", addr),
+            EmuError::InvalidRegister { index } => write!(f, "invalid register r{}", index),
+            EmuError::StackOverflow { pc } => write!(f, "stack overflow at pc {}", pc),
+            EmuError::StackUnderflow { pc } => write!(f, "stack underflow at pc {}", pc),
+            EmuError::ArithmeticOverflow { pc, code, lhs, rhs } => write!(
+                f,
+                "arithmetic overflow at pc {} in `{}` ({}, {})",
+                pc, code, lhs, rhs
+            ),
+            EmuError::InvalidInterruptLine { line } => {
+                write!(f, "interrupt line {} out of range", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+/// The status flags, set by `Add`, `Sub`, `Cmp`, `And`, `Or` and the shifts.
+///
+/// `carry` is the carry out of `Add` and the borrow of `Sub`/`Cmp`, so after
+/// `cmp ra, rb` it means `ra < rb` unsigned. Logic ops and shifts clear
+/// `overflow`; shifts put the bit shifted out into `carry`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    pub zero: bool,
+    pub carry: bool,
+    pub negative: bool,
+    pub overflow: bool,
+}
+
+impl Flags {
+    const ZERO: u16 = 0b0001;
+    const CARRY: u16 = 0b0010;
+    const NEGATIVE: u16 = 0b0100;
+    const OVERFLOW: u16 = 0b1000;
+
+    /// Zero and negative for `result`, carry and overflow cleared.
+    pub fn of(result: u16) -> Self {
+        Self {
+            zero: result == 0,
+            negative: result & 0x8000 != 0,
+            ..Self::default()
+        }
+    }
+
+    /// Wrapping `lhs + rhs` and its flags.
+    pub fn add(lhs: u16, rhs: u16) -> (u16, Self) {
+        let (result, carry) = lhs.overflowing_add(rhs);
+        let overflow = (lhs as i16).overflowing_add(rhs as i16).1;
+        (
+            result,
+            Self {
+                carry,
+                overflow,
+                ..Self::of(result)
+            },
+        )
+    }
+
+    /// Wrapping `lhs - rhs` and its flags.
+    pub fn sub(lhs: u16, rhs: u16) -> (u16, Self) {
+        let (result, carry) = lhs.overflowing_sub(rhs);
+        let overflow = (lhs as i16).overflowing_sub(rhs as i16).1;
+        (
+            result,
+            Self {
+                carry,
+                overflow,
+                ..Self::of(result)
+            },
+        )
+    }
+
+    /// Flags of a shift whose result is `result` and whose shifted-out bit is `carry`.
+    pub fn shift(result: u16, carry: bool) -> Self {
+        Self {
+            carry,
+            ..Self::of(result)
+        }
+    }
+
+    /// Signed `lhs < rhs` after `cmp lhs, rhs`.
+    pub fn less(self) -> bool {
+        self.negative != self.overflow
+    }
+
+    /// Packs the flags as `V N C Z` in bits 3..0.
+    pub fn bits(self) -> u16 {
+        let bit = |set: bool, mask: u16| if set { mask } else { 0 };
+        bit(self.zero, Self::ZERO)
+            | bit(self.carry, Self::CARRY)
+            | bit(self.negative, Self::NEGATIVE)
+            | bit(self.overflow, Self::OVERFLOW)
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        Self {
+            zero: bits & Self::ZERO != 0,
+            carry: bits & Self::CARRY != 0,
+            negative: bits & Self::NEGATIVE != 0,
+            overflow: bits & Self::OVERFLOW != 0,
+        }
+    }
+}
+
+impl fmt::Display for Flags {
+    /// `ZCNV` with `-` for each clear flag, e.g. `Z-N-`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+        write!(
+            f,
+            "{}{}{}{}",
+            flag(self.zero, 'Z'),
+            flag(self.carry, 'C'),
+            flag(self.negative, 'N'),
+            flag(self.overflow, 'V')
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        assert_eq!(Flags::add(1, 2), (3, Flags::default()));
+        assert_eq!(Flags::add(0xffff, 1).0, 0);
+        assert_eq!(Flags::add(0xffff, 1).1.to_string(), "ZC--");
+        assert_eq!(Flags::add(0x7fff, 1).1.to_string(), "--NV");
+    }
+
+    #[test]
+    fn test_sub() {
+        assert_eq!(Flags::sub(5, 5).1.to_string(), "Z---");
+        assert_eq!(Flags::sub(1, 2).1.to_string(), "-CN-");
+        assert_eq!(Flags::sub(0x8000, 1).1.to_string(), "---V");
+
+        // Signed: -1 < 1, but unsigned 0xffff > 1.
+        let (_, flags) = Flags::sub(0xffff, 1);
+        assert!(flags.less() && !flags.carry);
+    }
+
+    #[test]
+    fn test_bits() {
+        let flags = Flags::add(0xffff, 1).1;
+        assert_eq!(flags.bits(), 0b0011);
+        assert_eq!(Flags::from_bits(flags.bits()), flags);
+    }
+}
@@ -0,0 +1,178 @@
+use super::error::EmuError;
+use super::flags::Flags;
+use super::Addr;
+
+/// Number of interrupt lines; line 0 has the highest priority.
+pub const LINES: usize = 8;
+
+/// What `reti` restores when the running handler returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Saved {
+    /// Where execution resumes: the interrupted instruction's successor, or the
+    /// instruction after the one that faulted.
+    pub pc: Addr,
+    pub flags: Flags,
+    /// Whether interrupts were enabled on entry.
+    pub enabled: bool,
+    /// The fault that entered the trap vector; `None` for interrupts.
+    pub cause: Option<EmuError>,
+}
+
+/// Latched interrupt lines, their mask and the global enable toggled by `ei`/`di`.
+///
+/// Handlers do not nest: nothing is taken while `saved` is set, and a fault
+/// inside a handler aborts the run instead of re-entering the trap vector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptController {
+    pending: u8,
+    mask: u8,
+    enabled: bool,
+    saved: Option<Saved>,
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterruptController {
+    /// All lines unmasked, interrupts disabled until `ei`.
+    pub fn new() -> Self {
+        Self {
+            pending: 0,
+            mask: 0xff,
+            enabled: false,
+            saved: None,
+        }
+    }
+
+    /// Latches `line` until it is taken or cleared.
+    pub fn raise(&mut self, line: usize) -> Result<(), EmuError> {
+        self.pending |= bit(line)?;
+        Ok(())
+    }
+
+    /// Latches every line whose bit is set in `lines`.
+    pub fn raise_all(&mut self, lines: u8) {
+        self.pending |= lines;
+    }
+
+    pub fn clear(&mut self, line: usize) -> Result<(), EmuError> {
+        self.pending &= !bit(line)?;
+        Ok(())
+    }
+
+    /// Latched lines, bit `n` for line `n`.
+    pub fn pending(&self) -> u8 {
+        self.pending
+    }
+
+    /// Lines that may be taken, bit `n` for line `n`.
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    pub fn set_mask(&mut self, mask: u8) {
+        self.mask = mask;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// The state saved on entry to the running handler, if any.
+    pub fn saved(&self) -> Option<&Saved> {
+        self.saved.as_ref()
+    }
+
+    /// Takes the highest-priority line that is pending, unmasked and allowed now.
+    pub(super) fn acknowledge(&mut self) -> Option<usize> {
+        let ready = self.pending & self.mask;
+        if !self.enabled || self.saved.is_some() || ready == 0 {
+            return None;
+        }
+        let line = ready.trailing_zeros() as usize;
+        self.pending &= !(1 << line);
+        Some(line)
+    }
+
+    pub(super) fn enter(&mut self, saved: Saved) {
+        self.enabled = false;
+        self.saved = Some(saved);
+    }
+
+    pub(super) fn leave(&mut self) -> Option<Saved> {
+        let saved = self.saved.take()?;
+        self.enabled = saved.enabled;
+        Some(saved)
+    }
+}
+
+fn bit(line: usize) -> Result<u8, EmuError> {
+    if line < LINES {
+        Ok(1 << line)
+    } else {
+        Err(EmuError::InvalidInterruptLine { line })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved() -> Saved {
+        Saved {
+            pc: 3,
+            flags: Flags::default(),
+            enabled: true,
+            cause: None,
+        }
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut controller = InterruptController::new();
+        controller.raise(5).unwrap();
+        controller.raise(2).unwrap();
+        assert_eq!(controller.acknowledge(), None);
+
+        controller.set_enabled(true);
+        assert_eq!(controller.acknowledge(), Some(2));
+        assert_eq!(controller.pending(), 0b0010_0000);
+        assert_eq!(controller.acknowledge(), Some(5));
+        assert_eq!(controller.acknowledge(), None);
+    }
+
+    #[test]
+    fn test_mask_and_nesting() {
+        let mut controller = InterruptController::new();
+        controller.set_enabled(true);
+        controller.set_mask(0b1111_1110);
+        controller.raise(0).unwrap();
+        assert_eq!(controller.acknowledge(), None);
+
+        controller.raise(1).unwrap();
+        controller.enter(saved());
+        assert!(!controller.is_enabled());
+        assert_eq!(controller.acknowledge(), None);
+
+        assert_eq!(controller.leave(), Some(saved()));
+        assert!(controller.is_enabled());
+        assert_eq!(controller.acknowledge(), Some(1));
+        assert_eq!(controller.leave(), None);
+    }
+
+    #[test]
+    fn test_line_out_of_range() {
+        let mut controller = InterruptController::new();
+        let error = EmuError::InvalidInterruptLine { line: LINES };
+        assert_eq!(controller.raise(LINES), Err(error.clone()));
+        assert_eq!(controller.clear(LINES), Err(error));
+        assert_eq!(controller.pending(), 0);
+    }
+}
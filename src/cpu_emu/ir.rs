@@ -1,7 +1,10 @@
+use super::error::EmuError;
+use super::opcode::{Field, Opcode};
 use super::register::Slot;
 use super::{Addr, Data};
+use std::convert::TryFrom;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct InstructionRegister {
     instruction: u16,
 }
@@ -15,26 +18,32 @@ impl InstructionRegister {
         self.instruction = instruction;
     }
 
+    pub fn read(&self) -> u16 {
+        self.instruction
+    }
+
     pub fn code(&self) -> u16 {
-        self.instruction >> 11
+        Field::CODE.extract(self.instruction)
     }
 
-    pub fn reg_a(&self) -> Slot {
-        let index = self.instruction >> 8 & 0x0007; // 0 to 7
-        index.into()
+    pub fn reg_a(&self) -> Result<Slot, EmuError> {
+        Slot::try_from(Field::REG_A.extract(self.instruction))
     }
 
-    pub fn reg_b(&self) -> Slot {
-        let index = self.instruction >> 5 & 0x0007; // 0 to 7
-        index.into()
+    pub fn reg_b(&self) -> Result<Slot, EmuError> {
+        Slot::try_from(Field::REG_B.extract(self.instruction))
     }
 
     pub fn data(&self) -> Data {
-        self.instruction & 0x00ff
+        Field::IMM.extract(self.instruction)
     }
 
     pub fn addr(&self) -> Addr {
-        (self.instruction & 0x00ff) as Addr
+        Field::IMM.extract(self.instruction) as Addr
+    }
+
+    pub fn decode(&self) -> Result<Opcode, EmuError> {
+        Opcode::decode(self.instruction)
     }
 }
 
@@ -47,28 +56,28 @@ mod tests {
     fn test_reg_a_1() {
         let mut register = InstructionRegister::new();
         register.write(0b0000_000_000_00000);
-        assert_eq!(register.reg_a(), Slot::Reg0);
+        assert_eq!(register.reg_a(), Ok(Slot::Reg0));
     }
 
     #[test]
     fn test_reg_a_2() {
         let mut register = InstructionRegister::new();
         register.write(0b0000_111_000_00000);
-        assert_eq!(register.reg_a(), Slot::Reg7);
+        assert_eq!(register.reg_a(), Ok(Slot::Reg7));
     }
 
     #[test]
     fn test_reg_b_1() {
         let mut register = InstructionRegister::new();
         register.write(0b0000_000_001_00000);
-        assert_eq!(register.reg_b(), Slot::Reg1);
+        assert_eq!(register.reg_b(), Ok(Slot::Reg1));
     }
 
     #[test]
     fn test_reg_b_2() {
         let mut register = InstructionRegister::new();
         register.write(0b0000_000_011_00000);
-        assert_eq!(register.reg_b(), Slot::Reg3);
+        assert_eq!(register.reg_b(), Ok(Slot::Reg3));
     }
 
     #[test]
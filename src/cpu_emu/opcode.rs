@@ -1,22 +1,333 @@
+use super::error::EmuError;
 use super::register::Slot;
 use super::{Addr, Data};
+use std::convert::TryFrom;
+use std::fmt;
 
+/// A bit field of the 16-bit instruction word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub shift: u16,
+    pub width: u16,
+}
+
+impl Field {
+    pub const CODE: Field = Field::new(11, 5);
+    pub const REG_A: Field = Field::new(8, 3);
+    pub const REG_B: Field = Field::new(5, 3);
+    pub const IMM: Field = Field::new(0, 8);
+
+    const fn new(shift: u16, width: u16) -> Self {
+        Self { shift, width }
+    }
+
+    /// The bits of the word this field occupies.
+    pub const fn mask(self) -> u16 {
+        ((1 << self.width) - 1) << self.shift
+    }
+
+    pub const fn extract(self, word: u16) -> u16 {
+        (word & self.mask()) >> self.shift
+    }
+
+    pub const fn insert(self, value: u16) -> u16 {
+        (value << self.shift) & self.mask()
+    }
+}
+
+/// Which fields an instruction reads besides `Field::CODE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `code | reg_a | reg_b | -----`
+    RegReg,
+    /// `code | reg_a | ---_-----`
+    Reg,
+    /// `code | reg_a | data`
+    RegData,
+    /// `code | reg_a | addr`
+    RegAddr,
+    /// `code | --- | addr`
+    Addr,
+    /// `code | ---_---_-----`
+    Bare,
+    /// `code | reg_a | reg_b | -----`, where `reg_b` holds an address
+    RegInd,
+    /// `code | --- | reg_b | -----`, where `reg_b` holds an address
+    Ind,
+}
+
+impl Format {
+    /// Bits below `Field::CODE` that the decoder ignores for this format.
+    pub const fn dont_care(self) -> u16 {
+        let used = match self {
+            Format::RegReg => Field::REG_A.mask() | Field::REG_B.mask(),
+            Format::Reg => Field::REG_A.mask(),
+            Format::RegData | Format::RegAddr => Field::REG_A.mask() | Field::IMM.mask(),
+            Format::Addr => Field::IMM.mask(),
+            Format::Bare => 0,
+            Format::RegInd => Field::REG_A.mask() | Field::REG_B.mask(),
+            Format::Ind => Field::REG_B.mask(),
+        };
+        !(Field::CODE.mask() | used)
+    }
+
+    /// Whether the address operand is a register, written `[rb]` in assembly.
+    pub const fn is_indirect(self) -> bool {
+        matches!(self, Format::RegInd | Format::Ind)
+    }
+}
+
+/// The operand fields of an instruction word, decoded according to its `Format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    RegReg(Slot, Slot),
+    Reg(Slot),
+    RegData(Slot, Data),
+    RegAddr(Slot, Addr),
+    Addr(Addr),
+    Bare,
+    RegInd(Slot, Slot),
+    Ind(Slot),
+}
+
+impl Operands {
+    pub fn decode(format: Format, word: u16) -> Result<Self, EmuError> {
+        let reg_a = || Slot::try_from(Field::REG_A.extract(word));
+        let reg_b = || Slot::try_from(Field::REG_B.extract(word));
+        let imm = Field::IMM.extract(word);
+
+        let operands = match format {
+            Format::RegReg => Operands::RegReg(reg_a()?, reg_b()?),
+            Format::Reg => Operands::Reg(reg_a()?),
+            Format::RegData => Operands::RegData(reg_a()?, imm),
+            Format::RegAddr => Operands::RegAddr(reg_a()?, imm as Addr),
+            Format::Addr => Operands::Addr(imm as Addr),
+            Format::Bare => Operands::Bare,
+            Format::RegInd => Operands::RegInd(reg_a()?, reg_b()?),
+            Format::Ind => Operands::Ind(reg_b()?),
+        };
+
+        Ok(operands)
+    }
+
+    pub fn encode(&self) -> u16 {
+        let reg = |slot: Slot| slot as u16;
+
+        match *self {
+            Operands::RegReg(a, b) => Field::REG_A.insert(reg(a)) | Field::REG_B.insert(reg(b)),
+            Operands::Reg(a) => Field::REG_A.insert(reg(a)),
+            Operands::RegData(a, data) => Field::REG_A.insert(reg(a)) | Field::IMM.insert(data),
+            Operands::RegAddr(a, addr) => {
+                Field::REG_A.insert(reg(a)) | Field::IMM.insert(addr as u16)
+            }
+            Operands::Addr(addr) => Field::IMM.insert(addr as u16),
+            Operands::Bare => 0,
+            Operands::RegInd(a, b) => Field::REG_A.insert(reg(a)) | Field::REG_B.insert(reg(b)),
+            Operands::Ind(b) => Field::REG_B.insert(reg(b)),
+        }
+    }
+}
+
+impl fmt::Display for Operands {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operands::RegReg(a, b) => write!(f, "{}, {}", a, b),
+            Operands::Reg(a) => write!(f, "{}", a),
+            Operands::RegData(a, data) => write!(f, "{}, {}", a, data),
+            Operands::RegAddr(a, addr) => write!(f, "{}, {}", a, addr),
+            Operands::Addr(addr) => write!(f, "{}", addr),
+            Operands::Bare => Ok(()),
+            Operands::RegInd(a, b) => write!(f, "{}, [{}]", a, b),
+            Operands::Ind(b) => write!(f, "[{}]", b),
+        }
+    }
+}
+
+/// One row of the instruction format table.
 #[derive(Debug, PartialEq, Eq)]
-pub enum Opcode {
-    Mov(Slot, Slot),
-    Add(Slot, Slot),
-    Sub(Slot, Slot),
-    And(Slot, Slot),
-    Or(Slot, Slot),
-    Sl(Slot),
-    Sr(Slot),
-    Sra(Slot),
-    Ldl(Slot, Data),
-    Ldh(Slot, Data),
-    Cmp(Slot, Slot),
-    Je(Addr),
-    Jmp(Addr),
-    Ld(Slot, Addr),
-    St(Slot, Addr),
-    Hlt,
+pub struct Spec {
+    pub mnemonic: &'static str,
+    pub code: u16,
+    pub format: Format,
+}
+
+impl Spec {
+    pub fn by_code(code: u16) -> Option<&'static Spec> {
+        INSTRUCTIONS.iter().find(|spec| spec.code == code)
+    }
+
+    /// The first row spelled `mnemonic`; `ld`, `st` and `jmp` also have an
+    /// indirect row, see [`Spec::by_syntax`].
+    pub fn by_mnemonic(mnemonic: &str) -> Option<&'static Spec> {
+        INSTRUCTIONS
+            .iter()
+            .find(|spec| spec.mnemonic.eq_ignore_ascii_case(mnemonic))
+    }
+
+    /// The row spelled `mnemonic` whose address operand is a register if
+    /// `indirect`, falling back to [`Spec::by_mnemonic`].
+    pub fn by_syntax(mnemonic: &str, indirect: bool) -> Option<&'static Spec> {
+        INSTRUCTIONS
+            .iter()
+            .find(|spec| {
+                spec.mnemonic.eq_ignore_ascii_case(mnemonic)
+                    && spec.format.is_indirect() == indirect
+            })
+            .or_else(|| Spec::by_mnemonic(mnemonic))
+    }
+}
+
+/// Declares `Opcode`, its format table and the conversions between the two.
+///
+/// Each row names the variant with its operand bindings, the opcode value, the
+/// mnemonic and the `Format` whose `Operands` variant carries the same bindings.
+macro_rules! instruction_set {
+    ($(
+        $variant:ident $(($($arg:ident: $ty:ty),+))? = $code:literal, $mnemonic:literal, $format:ident;
+    )*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Opcode {
+            $($variant $(($($ty),+))?,)*
+        }
+
+        /// Every instruction of the ISA, in opcode order.
+        pub const INSTRUCTIONS: &[Spec] = &[
+            $(Spec { mnemonic: $mnemonic, code: $code, format: Format::$format },)*
+        ];
+
+        impl Opcode {
+            pub fn spec(&self) -> &'static Spec {
+                match self {
+                    $(Opcode::$variant { .. } => &Spec {
+                        mnemonic: $mnemonic,
+                        code: $code,
+                        format: Format::$format,
+                    },)*
+                }
+            }
+
+            pub fn operands(&self) -> Operands {
+                match *self {
+                    $(Opcode::$variant $(($($arg),+))? => Operands::$format $(($($arg),+))?,)*
+                }
+            }
+
+            /// Builds the instruction for `code` if `operands` match its format.
+            pub fn from_parts(code: u16, operands: Operands) -> Option<Opcode> {
+                match (code, operands) {
+                    $(($code, Operands::$format $(($($arg),+))?) => {
+                        Some(Opcode::$variant $(($($arg),+))?)
+                    })*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+instruction_set! {
+    Mov(a: Slot, b: Slot) = 0b0000, "mov", RegReg;
+    Add(a: Slot, b: Slot) = 0b0001, "add", RegReg;
+    Sub(a: Slot, b: Slot) = 0b0010, "sub", RegReg;
+    And(a: Slot, b: Slot) = 0b0011, "and", RegReg;
+    Or(a: Slot, b: Slot) = 0b0100, "or", RegReg;
+    Sl(a: Slot) = 0b0101, "sl", Reg;
+    Sr(a: Slot) = 0b0110, "sr", Reg;
+    Sra(a: Slot) = 0b0111, "sra", Reg;
+    Ldl(a: Slot, data: Data) = 0b1000, "ldl", RegData;
+    Ldh(a: Slot, data: Data) = 0b1001, "ldh", RegData;
+    Cmp(a: Slot, b: Slot) = 0b1010, "cmp", RegReg;
+    Je(addr: Addr) = 0b1011, "je", Addr;
+    Jmp(addr: Addr) = 0b1100, "jmp", Addr;
+    Ld(a: Slot, addr: Addr) = 0b1101, "ld", RegAddr;
+    St(a: Slot, addr: Addr) = 0b1110, "st", RegAddr;
+    Hlt = 0b1111, "hlt", Bare;
+    Jne(addr: Addr) = 0b10000, "jne", Addr;
+    Jlt(addr: Addr) = 0b10001, "jlt", Addr;
+    Jge(addr: Addr) = 0b10010, "jge", Addr;
+    Jc(addr: Addr) = 0b10011, "jc", Addr;
+    Jnc(addr: Addr) = 0b10100, "jnc", Addr;
+    Push(a: Slot) = 0b10101, "push", Reg;
+    Pop(a: Slot) = 0b10110, "pop", Reg;
+    Call(addr: Addr) = 0b10111, "call", Addr;
+    Ret = 0b11000, "ret", Bare;
+    LdInd(a: Slot, b: Slot) = 0b11001, "ld", RegInd;
+    StInd(a: Slot, b: Slot) = 0b11010, "st", RegInd;
+    JmpInd(b: Slot) = 0b11011, "jmp", Ind;
+    Ei = 0b11100, "ei", Bare;
+    Di = 0b11101, "di", Bare;
+    Reti = 0b11110, "reti", Bare;
+}
+
+impl Opcode {
+    pub fn decode(word: u16) -> Result<Opcode, EmuError> {
+        let spec = Spec::by_code(Field::CODE.extract(word))
+            .ok_or(EmuError::IllegalInstruction { word })?;
+        let operands = Operands::decode(spec.format, word)?;
+        Ok(Opcode::from_parts(spec.code, operands).expect("format table is consistent"))
+    }
+
+    pub fn encode(&self) -> u16 {
+        Field::CODE.insert(self.spec().code) | self.operands().encode()
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.spec().mnemonic
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operands() {
+            Operands::Bare => write!(f, "{}", self.mnemonic()),
+            operands => write!(f, "{} {}", self.mnemonic(), operands),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_table_is_unique() {
+        let codes: HashSet<_> = INSTRUCTIONS.iter().map(|spec| spec.code).collect();
+        let syntaxes: HashSet<_> = INSTRUCTIONS
+            .iter()
+            .map(|spec| (spec.mnemonic, spec.format.is_indirect()))
+            .collect();
+        assert_eq!(codes.len(), INSTRUCTIONS.len());
+        assert_eq!(syntaxes.len(), INSTRUCTIONS.len());
+    }
+
+    #[test]
+    fn test_encode_decode_all_words() {
+        for word in 0..=u16::MAX {
+            match Opcode::decode(word) {
+                Ok(code) => {
+                    let dont_care = code.spec().format.dont_care();
+                    assert_eq!(code.encode(), word & !dont_care, "word {:#06x}", word);
+                    assert_eq!(Opcode::decode(code.encode()), Ok(code));
+                }
+                Err(_) => assert!(Spec::by_code(Field::CODE.extract(word)).is_none()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Opcode::Mov(Slot::Reg0, Slot::Reg1).to_string(),
+            "mov r0, r1"
+        );
+        assert_eq!(Opcode::Ldl(Slot::Reg3, 10).to_string(), "ldl r3, 10");
+        assert_eq!(Opcode::Je(14).to_string(), "je 14");
+        assert_eq!(Opcode::Hlt.to_string(), "hlt");
+        assert_eq!(
+            Opcode::LdInd(Slot::Reg0, Slot::Reg1).to_string(),
+            "ld r0, [r1]"
+        );
+        assert_eq!(Opcode::JmpInd(Slot::Reg2).to_string(), "jmp [r2]");
+    }
 }
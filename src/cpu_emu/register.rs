@@ -1,5 +1,8 @@
+use super::error::EmuError;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+use std::convert::TryFrom;
+use std::fmt;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
 pub enum Slot {
@@ -13,9 +16,30 @@ pub enum Slot {
     Reg7,
 }
 
-impl From<u16> for Slot {
-    fn from(from: u16) -> Slot {
-        FromPrimitive::from_u16(from).unwrap()
+impl Slot {
+    pub const ALL: [Slot; 8] = [
+        Slot::Reg0,
+        Slot::Reg1,
+        Slot::Reg2,
+        Slot::Reg3,
+        Slot::Reg4,
+        Slot::Reg5,
+        Slot::Reg6,
+        Slot::Reg7,
+    ];
+}
+
+impl TryFrom<u16> for Slot {
+    type Error = EmuError;
+
+    fn try_from(index: u16) -> Result<Slot, EmuError> {
+        FromPrimitive::from_u16(index).ok_or(EmuError::InvalidRegister { index })
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "r{}", *self as u16)
     }
 }
 
@@ -36,6 +60,10 @@ impl GeneralRegister {
     pub fn write(&mut self, slot: Slot, data: u16) {
         self.regs[slot as usize] = data;
     }
+
+    pub fn all(&self) -> [u16; 8] {
+        self.regs
+    }
 }
 
 #[cfg(test)]
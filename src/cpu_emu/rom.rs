@@ -1,3 +1,5 @@
+use super::error::EmuError;
+
 #[derive(Debug)]
 pub struct Rom {
     data: Vec<u16>,
@@ -8,13 +10,27 @@ impl Rom {
         Self { data }
     }
 
-    pub fn read(&self, index: usize) -> Result<u16, String> {
+    pub fn words(&self) -> &[u16] {
+        &self.data
+    }
+
+    pub fn read(&self, index: usize) -> Result<u16, EmuError> {
         if self.data.len() > index {
             Ok(self.data[index])
         } else {
-            Err("Unexpected EOF".to_string())
+            Err(EmuError::RomOutOfBounds { pc: index })
         }
     }
+
+    /// Patches one word, e.g. for a debugger; the ROM never grows.
+    pub fn write(&mut self, index: usize, word: u16) -> Result<(), EmuError> {
+        let slot = self
+            .data
+            .get_mut(index)
+            .ok_or(EmuError::RomOutOfBounds { pc: index })?;
+        *slot = word;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -26,4 +42,18 @@ mod tests {
         let rom = Rom::new(vec![0x0010, 0x0012]);
         assert_eq!(rom.read(0), Ok(0x0010));
     }
+
+    #[test]
+    fn test_read_out_of_bounds() {
+        let rom = Rom::new(vec![0x0010, 0x0012]);
+        assert_eq!(rom.read(2), Err(EmuError::RomOutOfBounds { pc: 2 }));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut rom = Rom::new(vec![0x0010, 0x0012]);
+        assert_eq!(rom.write(1, 0x7800), Ok(()));
+        assert_eq!(rom.words(), &[0x0010, 0x7800]);
+        assert_eq!(rom.write(2, 0), Err(EmuError::RomOutOfBounds { pc: 2 }));
+    }
 }
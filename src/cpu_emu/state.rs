@@ -0,0 +1,99 @@
+use super::flags::Flags;
+use super::register::Slot;
+use super::Addr;
+use std::fmt;
+
+/// A copy of the architectural state of a `CpuEmu` at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub pc: Addr,
+    pub sp: Addr,
+    pub cycles: u64,
+    pub registers: [u16; 8],
+    pub flags: Flags,
+    pub halted: bool,
+    pub ram: Vec<u16>,
+}
+
+impl MachineState {
+    pub fn register(&self, slot: Slot) -> u16 {
+        self.registers[slot as usize]
+    }
+}
+
+const RAM_ROW: usize = 8;
+
+impl fmt::Display for MachineState {
+    /// Registers first, then RAM in rows of eight words; all-zero rows are
+    /// collapsed into a single `*` line like `hexdump` does.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pc: {:>3}  sp: {:>3}  flags: {}  cycles: {}",
+            self.pc, self.sp, self.flags, self.cycles
+        )?;
+        if self.halted {
+            write!(f, "  halted")?;
+        }
+        writeln!(f)?;
+
+        for row in Slot::ALL.chunks(4) {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|&slot| {
+                    let value = self.register(slot);
+                    format!("{}: {:#06x} ({:>5})", slot, value, value)
+                })
+                .collect();
+            writeln!(f, "{}", cells.join("  "))?;
+        }
+
+        writeln!(f, "ram:")?;
+        let mut skipping = false;
+        for (index, row) in self.ram.chunks(RAM_ROW).enumerate() {
+            if row.iter().all(|&word| word == 0) {
+                if !skipping {
+                    writeln!(f, "  *")?;
+                }
+                skipping = true;
+                continue;
+            }
+            skipping = false;
+
+            let words: Vec<String> = row.iter().map(|word| format!("{:04x}", word)).collect();
+            writeln!(f, "  {:#04x}: {}", index * RAM_ROW, words.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut ram = vec![0; 32];
+        ram[17] = 0x0037;
+        let state = MachineState {
+            pc: 14,
+            sp: 256,
+            cycles: 42,
+            registers: [1, 10, 10, 55, 0, 0, 0, 0xffff],
+            flags: Flags::from_bits(0b0001),
+            halted: true,
+            ram,
+        };
+
+        assert_eq!(
+            state.to_string(),
+            "pc:  14  sp: 256  flags: Z---  cycles: 42  halted\n\
+             r0: 0x0001 (    1)  r1: 0x000a (   10)  r2: 0x000a (   10)  r3: 0x0037 (   55)\n\
+             r4: 0x0000 (    0)  r5: 0x0000 (    0)  r6: 0x0000 (    0)  r7: 0xffff (65535)\n\
+             ram:\n\
+             \x20 *\n\
+             \x20 0x10: 0000 0037 0000 0000 0000 0000 0000 0000\n\
+             \x20 *\n"
+        );
+    }
+}
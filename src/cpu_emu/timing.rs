@@ -0,0 +1,116 @@
+use super::opcode::{Opcode, Spec, INSTRUCTIONS};
+
+/// Cycles one instruction takes: `base`, plus `taken` when it jumps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Cost {
+    pub base: u64,
+    pub taken: u64,
+}
+
+impl Cost {
+    pub const fn new(base: u64, taken: u64) -> Self {
+        Self { base, taken }
+    }
+}
+
+const CODES: usize = 1 << 5;
+
+/// Instruction timing, one `Cost` per opcode.
+///
+/// An instruction counts as taken when it leaves the PC anywhere but the next
+/// word, so a conditional jump to the following instruction costs `base` only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timing {
+    costs: [Cost; CODES],
+    /// Cycles to enter an interrupt or trap handler, on top of the instruction.
+    pub interrupt: u64,
+}
+
+impl Default for Timing {
+    /// Register ops take 1 cycle, memory and stack ops 2, `jmp` 2, `call`,
+    /// `ret` and `reti` 3, and conditional jumps 1 plus 1 more when taken.
+    /// Entering a handler takes 2.
+    fn default() -> Self {
+        let mut timing = Timing::uniform();
+        for spec in INSTRUCTIONS {
+            let cost = match spec.mnemonic {
+                "ld" | "st" | "push" | "pop" | "jmp" => Cost::new(2, 0),
+                "call" | "ret" | "reti" => Cost::new(3, 0),
+                mnemonic if mnemonic.starts_with('j') => Cost::new(1, 1),
+                _ => Cost::new(1, 0),
+            };
+            timing.set(spec, cost);
+        }
+        timing.interrupt = 2;
+        timing
+    }
+}
+
+impl Timing {
+    /// One cycle per instruction, whatever it does, and free handler entry.
+    pub fn uniform() -> Self {
+        Self {
+            costs: [Cost::new(1, 0); CODES],
+            interrupt: 0,
+        }
+    }
+
+    /// Nothing takes any cycles, for a caller that advances the clock itself
+    /// with `CpuEmu::elapse`.
+    pub fn zero() -> Self {
+        Self {
+            costs: [Cost::default(); CODES],
+            interrupt: 0,
+        }
+    }
+
+    pub fn cost(&self, spec: &Spec) -> Cost {
+        self.costs[spec.code as usize]
+    }
+
+    pub fn set(&mut self, spec: &Spec, cost: Cost) {
+        self.costs[spec.code as usize] = cost;
+    }
+
+    /// Cycles `code` took, given whether it jumped.
+    pub fn cycles(&self, code: &Opcode, taken: bool) -> u64 {
+        let cost = self.cost(code.spec());
+        if taken {
+            cost.base + cost.taken
+        } else {
+            cost.base
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_emu::Slot;
+
+    #[test]
+    fn test_default() {
+        let timing = Timing::default();
+        assert_eq!(
+            timing.cycles(&Opcode::Add(Slot::Reg0, Slot::Reg1), false),
+            1
+        );
+        assert_eq!(timing.cycles(&Opcode::Ld(Slot::Reg0, 4), false), 2);
+        assert_eq!(
+            timing.cycles(&Opcode::StInd(Slot::Reg0, Slot::Reg1), false),
+            2
+        );
+        assert_eq!(timing.cycles(&Opcode::Je(4), false), 1);
+        assert_eq!(timing.cycles(&Opcode::Je(4), true), 2);
+        assert_eq!(timing.cycles(&Opcode::Jmp(4), true), 2);
+        assert_eq!(timing.cycles(&Opcode::Call(4), true), 3);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut timing = Timing::uniform();
+        timing.set(Spec::by_mnemonic("hlt").unwrap(), Cost::new(5, 0));
+        assert_eq!(timing.cycles(&Opcode::Hlt, false), 5);
+        assert_eq!(timing.cycles(&Opcode::Jne(0), true), 1);
+    }
+}
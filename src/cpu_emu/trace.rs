@@ -0,0 +1,238 @@
+use super::flags::Flags;
+use super::opcode::Opcode;
+use super::register::Slot;
+use super::Addr;
+#[cfg(test)]
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+#[cfg(test)]
+use std::rc::Rc;
+
+/// What one executed instruction did to the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// 1-based count of executed instructions.
+    pub step: u64,
+    pub pc: Addr,
+    pub word: u16,
+    pub code: Opcode,
+    /// Register values after the instruction.
+    pub registers: [u16; 8],
+    /// Registers whose value changed, with their new value.
+    pub changed: Vec<(Slot, u16)>,
+    pub flags: Flags,
+    /// RAM writes as `(addr, data)`.
+    pub writes: Vec<(Addr, u16)>,
+    pub next_pc: Addr,
+}
+
+impl TraceEvent {
+    /// Lists the registers that differ between `before` and `after`.
+    pub fn diff(before: &[u16; 8], after: &[u16; 8]) -> Vec<(Slot, u16)> {
+        Slot::ALL
+            .iter()
+            .filter(|&&slot| before[slot as usize] != after[slot as usize])
+            .map(|&slot| (slot, after[slot as usize]))
+            .collect()
+    }
+}
+
+/// Receives one event per executed instruction from `CpuEmu`.
+///
+/// The built-in tracers ignore write errors so that tracing never changes how
+/// a program runs.
+pub trait Tracer {
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+impl fmt::Debug for dyn Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+/// Human-readable columns: step, pc, encoding, disassembly, then what changed.
+pub struct TextTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        let mut effects: Vec<String> = event
+            .changed
+            .iter()
+            .map(|(slot, value)| format!("{}={}", slot, value))
+            .collect();
+        effects.extend(
+            event
+                .writes
+                .iter()
+                .map(|(addr, data)| format!("ram[{}]={}", addr, data)),
+        );
+
+        let line = format!(
+            "{:>6} {:>3} {:04x}  {:<14} flags={} {}",
+            event.step,
+            event.pc,
+            event.word,
+            event.code.to_string(),
+            event.flags,
+            effects.join(" ")
+        );
+        let _ = writeln!(self.out, "{}", line.trim_end());
+    }
+}
+
+/// One CSV row per instruction with the full register file, for diffing runs.
+pub struct CsvTracer<W: Write> {
+    out: W,
+    header: bool,
+}
+
+impl<W: Write> CsvTracer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, header: false }
+    }
+}
+
+impl<W: Write> Tracer for CsvTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        if !self.header {
+            self.header = true;
+            let _ = writeln!(
+                self.out,
+                "step,pc,word,instruction,r0,r1,r2,r3,r4,r5,r6,r7,flags,writes,next_pc"
+            );
+        }
+
+        let registers: Vec<String> = event.registers.iter().map(u16::to_string).collect();
+        let writes: Vec<String> = event
+            .writes
+            .iter()
+            .map(|(addr, data)| format!("{}={}", addr, data))
+            .collect();
+        let _ = writeln!(
+            self.out,
+            "{},{},{:#06x},\"{}\",{},{},{},{}",
+            event.step,
+            event.pc,
+            event.word,
+            event.code,
+            registers.join(","),
+            event.flags,
+            writes.join(";"),
+            event.next_pc
+        );
+    }
+}
+
+/// One JSON object per line.
+pub struct JsonTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        let changed: Vec<String> = event
+            .changed
+            .iter()
+            .map(|(slot, value)| format!("\"{}\":{}", slot, value))
+            .collect();
+        let writes: Vec<String> = event
+            .writes
+            .iter()
+            .map(|(addr, data)| format!("{{\"addr\":{},\"data\":{}}}", addr, data))
+            .collect();
+        let _ = writeln!(
+            self.out,
+            "{{\"step\":{},\"pc\":{},\"word\":{},\"instruction\":\"{}\",\"changed\":{{{}}},\"flags\":\"{}\",\"writes\":[{}],\"next_pc\":{}}}",
+            event.step,
+            event.pc,
+            event.word,
+            event.code,
+            changed.join(","),
+            event.flags,
+            writes.join(","),
+            event.next_pc
+        );
+    }
+}
+
+/// Keeps every event; clones share the list, so a test can hand one to
+/// `CpuEmu::set_tracer` and read the events through another.
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub struct Collect(pub Rc<RefCell<Vec<TraceEvent>>>);
+
+#[cfg(test)]
+impl Tracer for Collect {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.0.borrow_mut().push(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> TraceEvent {
+        TraceEvent {
+            step: 5,
+            pc: 4,
+            word: 0b1110_011_01000000,
+            code: Opcode::St(Slot::Reg3, 64),
+            registers: [1, 10, 1, 1, 0, 0, 0, 0],
+            changed: vec![(Slot::Reg3, 1)],
+            flags: Flags::default(),
+            writes: vec![(64, 1)],
+            next_pc: 5,
+        }
+    }
+
+    fn render<T: Tracer>(mut tracer: T, take: impl FnOnce(T) -> Vec<u8>) -> String {
+        tracer.trace(&event());
+        String::from_utf8(take(tracer)).unwrap()
+    }
+
+    #[test]
+    fn test_text() {
+        let text = render(TextTracer::new(Vec::new()), |t| t.out);
+        assert_eq!(
+            text,
+            "     5   4 7340  st r3, 64      flags=---- r3=1 ram[64]=1\n"
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let text = render(CsvTracer::new(Vec::new()), |t| t.out);
+        assert_eq!(
+            text,
+            "step,pc,word,instruction,r0,r1,r2,r3,r4,r5,r6,r7,flags,writes,next_pc\n\
+             5,4,0x7340,\"st r3, 64\",1,10,1,1,0,0,0,0,----,64=1,5\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let text = render(JsonTracer::new(Vec::new()), |t| t.out);
+        assert_eq!(
+            text,
+            "{\"step\":5,\"pc\":4,\"word\":29504,\"instruction\":\"st r3, 64\",\
+             \"changed\":{\"r3\":1},\"flags\":\"----\",\"writes\":[{\"addr\":64,\"data\":1}],\"next_pc\":5}\n"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use super::register::Slot;
+use super::Addr;
+use std::fmt;
+
+/// A data access that stops the run loop once the instruction causing it completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// `Ld` from this data address.
+    Read(Addr),
+    /// `St` to this data address, whether or not the value changes.
+    Write(Addr),
+    /// Any instruction that changes this register's value.
+    Register(Slot),
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Read(addr) => write!(f, "read ram[{}]", addr),
+            Watchpoint::Write(addr) => write!(f, "write ram[{}]", addr),
+            Watchpoint::Register(slot) => write!(f, "change {}", slot),
+        }
+    }
+}
+
+/// Which watchpoint fired, and where.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    /// Address of the instruction that triggered it.
+    pub pc: Addr,
+    /// Value before the access; the value read for `Read`. `None` for a write
+    /// to a device, whose registers cannot be read without side effects.
+    pub old: Option<u16>,
+    /// Value after the access.
+    pub new: u16,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.watchpoint {
+            Watchpoint::Read(_) => write!(
+                f,
+                "watchpoint {} at pc {}: {}",
+                self.watchpoint, self.pc, self.new
+            ),
+            _ => match self.old {
+                Some(old) => write!(
+                    f,
+                    "watchpoint {} at pc {}: {} -> {}",
+                    self.watchpoint, self.pc, old, self.new
+                ),
+                None => write!(
+                    f,
+                    "watchpoint {} at pc {}: {}",
+                    self.watchpoint, self.pc, self.new
+                ),
+            },
+        }
+    }
+}
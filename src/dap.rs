@@ -0,0 +1,763 @@
+//! A Debug Adapter Protocol server for the emulated program, spoken over any
+//! reader/writer pair (stdin/stdout in `rdap`).
+//!
+//! Breakpoints are set on source lines when the program was assembled from a
+//! `.s`/`.asm` file. Images loaded from disk have no line map; their "source"
+//! is the disassembly listing, where line `n` is ROM address `n - 1`.
+
+use crate::asm::Program;
+use crate::cpu_emu::{CpuEmu, Opcode, Rom, Slot, StopReason};
+use crate::debugger::NEXT_BUDGET;
+use crate::disasm::{self, Disassembled};
+use crate::image::{self, ImageFormat};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+/// Instructions run between checks for new requests (e.g. `pause`) while running.
+const CHUNK: usize = 4096;
+/// Largest message body accepted, far above any request a client sends.
+const MAX_MESSAGE: usize = 1 << 20;
+
+const THREAD: i64 = 1;
+const REGISTERS: i64 = 1;
+const RAM: i64 = 2;
+const LISTING: i64 = 1;
+
+struct Session {
+    cpu: CpuEmu,
+    path: PathBuf,
+    lines: Vec<usize>,
+    stop_on_entry: bool,
+    /// Breakpoints the client asked for, as ROM addresses.
+    breakpoints: BTreeSet<usize>,
+    /// `sp` when `stepOut` was requested; the step ends once `ret` pops above it.
+    step_out: Option<usize>,
+}
+
+impl Session {
+    fn line(&self, addr: usize) -> usize {
+        match self.lines.get(addr) {
+            Some(&line) => line,
+            None if self.lines.is_empty() => addr + 1,
+            None => self.lines.last().map_or(1, |&line| line + 1),
+        }
+    }
+
+    /// The first instruction at or after `line`.
+    fn addr(&self, line: usize) -> Option<usize> {
+        if self.lines.is_empty() {
+            let addr = line.checked_sub(1)?;
+            return (addr < self.cpu.rom().words().len()).then_some(addr);
+        }
+        self.lines.iter().position(|&at| at >= line)
+    }
+
+    /// Runs up to `CHUNK` instructions, stopping early once a `stepOut` is done.
+    fn run_chunk(&mut self) -> StopReason {
+        let sp = match self.step_out {
+            Some(sp) => sp,
+            None => return self.cpu.run_for(CHUNK),
+        };
+        let is_ret =
+            |cpu: &CpuEmu| cpu.rom().read(cpu.pc()).and_then(Opcode::decode) == Ok(Opcode::Ret);
+        let mut returning = is_ret(&self.cpu);
+        let mut returned = false;
+        let mut left = CHUNK;
+        let reason = self.cpu.run_until(|cpu| {
+            left -= 1;
+            returned = returning && cpu.sp() > sp;
+            returning = is_ret(cpu);
+            returned || left == 0
+        });
+        match reason {
+            // Out of budget, not returned yet and not at a breakpoint either.
+            StopReason::Breakpoint(pc)
+                if !returned && !self.cpu.breakpoints().any(|at| at == pc) =>
+            {
+                StopReason::BudgetExhausted
+            }
+            reason => reason,
+        }
+    }
+
+    fn source(&self) -> Value {
+        let name = self
+            .path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        if self.lines.is_empty() {
+            json!({ "name": format!("{} (disassembly)", name), "sourceReference": LISTING })
+        } else {
+            json!({ "name": name, "path": self.path })
+        }
+    }
+}
+
+pub struct DapServer<W: Write> {
+    out: W,
+    seq: i64,
+    session: Option<Session>,
+    running: bool,
+    finished: bool,
+    /// Why the last `stepIn` or `next` stopped, reported after its response.
+    stepped: Option<StopReason>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            seq: 0,
+            session: None,
+            running: false,
+            finished: false,
+            stepped: None,
+        }
+    }
+
+    /// True while the program runs and `run_chunk` should be called.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// True once the client disconnected.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": false,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => self.session().map(|_| Value::Null),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "cpu" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "RAM", "variablesReference": RAM, "indexedVariables": 256, "expensive": true },
+            ] })),
+            "variables" => self.variables(args),
+            "source" => self.listing(),
+            "continue" => self
+                .resume()
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.next(),
+            "stepIn" => self.step(),
+            "stepOut" => self.step_out(),
+            "pause" => self.pause(),
+            "disconnect" | "terminate" => {
+                self.finished = true;
+                self.running = false;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("unsupported request `{}`", command)),
+        };
+
+        let ok = result.is_ok();
+        self.respond(request, result)?;
+        if !ok {
+            return Ok(());
+        }
+        match command {
+            "initialize" => self.event("initialized", Value::Null),
+            "configurationDone" if self.session.as_ref().is_some_and(|s| s.stop_on_entry) => {
+                self.stopped("entry", None)
+            }
+            "configurationDone" => {
+                self.running = true;
+                Ok(())
+            }
+            "stepIn" | "next" => match self.stepped.take() {
+                Some(reason) => self.report(reason),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs up to `CHUNK` instructions and reports if the program stopped.
+    pub fn run_chunk(&mut self) -> io::Result<()> {
+        let reason = match self.session.as_mut() {
+            Some(session) if self.running => session.run_chunk(),
+            _ => return Ok(()),
+        };
+        match reason {
+            StopReason::BudgetExhausted => Ok(()),
+            reason => self.report(reason),
+        }
+    }
+
+    fn session(&self) -> Result<&Session, String> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| "no program launched".to_string())
+    }
+
+    fn session_mut(&mut self) -> Result<&mut Session, String> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| "no program launched".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["program"]
+            .as_str()
+            .ok_or("launch needs a `program` path")?;
+        let format = match args["format"].as_str() {
+            Some(name) => Some(
+                ImageFormat::from_name(name).ok_or_else(|| format!("unknown format `{}`", name))?,
+            ),
+            None => None,
+        };
+        let Program { words, lines, .. } = image::open(Path::new(path), format)?;
+
+        self.session = Some(Session {
+            cpu: CpuEmu::new(Rom::new(words)),
+            path: PathBuf::from(path),
+            lines,
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            breakpoints: BTreeSet::new(),
+            step_out: None,
+        });
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let session = self.session_mut()?;
+        for addr in std::mem::take(&mut session.breakpoints) {
+            session.cpu.remove_breakpoint(addr);
+        }
+
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Value> = requested
+            .iter()
+            .map(|breakpoint| {
+                let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+                match session.addr(line) {
+                    Some(addr) => {
+                        session.breakpoints.insert(addr);
+                        session.cpu.add_breakpoint(addr);
+                        json!({ "verified": true, "line": session.line(addr) })
+                    }
+                    None => {
+                        json!({ "verified": false, "line": line, "message": "no instruction here" })
+                    }
+                }
+            })
+            .collect();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let session = self.session()?;
+        let pc = session.cpu.pc();
+        let name = match session.cpu.rom().read(pc) {
+            Ok(word) => Disassembled::new(pc, word).source(),
+            Err(_) => format!("pc {}", pc),
+        };
+        Ok(json!({
+            "stackFrames": [{
+                "id": 1,
+                "name": name,
+                "source": session.source(),
+                "line": session.line(pc),
+                "column": 1,
+                "instructionPointerReference": format!("{:#x}", pc),
+            }],
+            "totalFrames": 1,
+        }))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let cpu = &self.session()?.cpu;
+        let variable = |name: String, value: u16| json!({ "name": name, "value": format!("{} ({:#06x})", value, value), "variablesReference": 0 });
+
+        let variables: Vec<Value> = match args["variablesReference"].as_i64() {
+            Some(REGISTERS) => {
+                let mut variables: Vec<Value> = Slot::ALL
+                    .iter()
+                    .map(|&slot| variable(slot.to_string(), cpu.register(slot)))
+                    .collect();
+                variables.push(variable("pc".to_string(), cpu.pc() as u16));
+                variables.push(json!({
+                    "name": "flags",
+                    "value": cpu.flags().to_string(),
+                    "variablesReference": 0,
+                }));
+                variables.push(variable("sp".to_string(), cpu.sp() as u16));
+                variables
+            }
+            Some(RAM) => {
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = args["count"].as_u64().map_or(256, |count| count as usize);
+                let end = start.saturating_add(count).min(256);
+                let ram = cpu
+                    .ram(start.min(end)..end)
+                    .map_err(|err| err.to_string())?;
+                ram.iter()
+                    .enumerate()
+                    .map(|(offset, &word)| variable(format!("[{:#04x}]", start + offset), word))
+                    .collect()
+            }
+            _ => return Err("unknown variables reference".to_string()),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn listing(&self) -> Result<Value, String> {
+        Ok(json!({ "content": disasm::listing(self.session()?.cpu.rom()) }))
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        self.session()?;
+        self.running = true;
+        Ok(())
+    }
+
+    /// Steps like `stepIn`, but runs a `call` until it returns; a call that
+    /// does not return within `NEXT_BUDGET` instructions stops where it got to.
+    fn next(&mut self) -> Result<Value, String> {
+        let reason = self.session_mut()?.cpu.step_over(NEXT_BUDGET);
+        self.stepped = Some(reason.unwrap_or(StopReason::BudgetExhausted));
+        Ok(Value::Null)
+    }
+
+    fn step(&mut self) -> Result<Value, String> {
+        self.stepped = Some(self.session_mut()?.cpu.step());
+        Ok(Value::Null)
+    }
+
+    /// Runs until the current subroutine returns: `ret` pops the return
+    /// address and leaves `sp` above where it is now.
+    fn step_out(&mut self) -> Result<Value, String> {
+        let session = self.session_mut()?;
+        session.step_out = Some(session.cpu.sp());
+        self.running = true;
+        Ok(Value::Null)
+    }
+
+    fn pause(&mut self) -> Result<Value, String> {
+        self.session()?;
+        if self.running {
+            self.running = false;
+            self.clear_step();
+            self.stopped("pause", None).map_err(|err| err.to_string())?;
+        }
+        Ok(Value::Null)
+    }
+
+    /// Forgets a `stepOut` in progress.
+    fn clear_step(&mut self) {
+        if let Some(session) = self.session.as_mut() {
+            session.step_out = None;
+        }
+    }
+
+    fn report(&mut self, reason: StopReason) -> io::Result<()> {
+        self.running = false;
+        let stepped_out = self
+            .session
+            .as_ref()
+            .is_some_and(|session| session.step_out.is_some());
+        self.clear_step();
+        let user = |addr: usize, server: &Self| {
+            server
+                .session
+                .as_ref()
+                .is_some_and(|session| session.breakpoints.contains(&addr))
+        };
+
+        match reason {
+            StopReason::Halted => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                self.event("terminated", Value::Null)
+            }
+            StopReason::Breakpoint(addr) if stepped_out && !user(addr, self) => {
+                self.stopped("step", None)
+            }
+            StopReason::Breakpoint(_) => self.stopped("breakpoint", None),
+            StopReason::BudgetExhausted => self.stopped("step", None),
+            StopReason::Watchpoint(hit) => self.stopped("data breakpoint", Some(hit.to_string())),
+            StopReason::Fault(err) => self.stopped("exception", Some(err.to_string())),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.out, &message)
+    }
+}
+
+/// Reads one `Content-Length`-framed message; `None` at end of input.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Content-Length {} is too large", length),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message<W: Write>(out: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+/// Serves requests from `input` until the client disconnects or closes it.
+///
+/// Requests are read on a separate thread so `pause` arrives while the
+/// program runs.
+pub fn serve<R, W>(mut input: R, out: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut server = DapServer::new(out);
+    loop {
+        let request = if server.is_running() {
+            match receiver.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(request) => Some(request),
+                Err(_) => break,
+            }
+        };
+
+        if let Some(request) = request {
+            server.handle(&request)?;
+            if server.is_finished() {
+                break;
+            }
+        }
+        server.run_chunk()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+
+    const PROGRAM: &str = "\
+    ldl r0, 1
+    ldl r1, 3
+loop:
+    add r2, r0
+    st r2, 64
+    cmp r1, r2
+    je done
+    jmp loop
+done:
+    hlt
+";
+
+    struct Client {
+        server: DapServer<Vec<u8>>,
+        seq: i64,
+    }
+
+    impl Client {
+        fn launch(path: &Path) -> Self {
+            let mut client = Client {
+                server: DapServer::new(Vec::new()),
+                seq: 0,
+            };
+            client.request("initialize", json!({}));
+            client.request("launch", json!({ "program": path, "stopOnEntry": true }));
+            client
+        }
+
+        /// Sends a request and returns everything the server wrote in reply.
+        fn request(&mut self, command: &str, arguments: Value) -> Vec<Value> {
+            self.seq += 1;
+            let request = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            self.server.handle(&request).unwrap();
+            while self.server.is_running() {
+                self.server.run_chunk().unwrap();
+            }
+            let mut output = Cursor::new(std::mem::take(&mut self.server.out));
+            let mut messages = Vec::new();
+            while let Some(message) = read_message(&mut output).unwrap() {
+                messages.push(message);
+            }
+            messages
+        }
+
+        fn events(&mut self, command: &str, arguments: Value) -> Vec<String> {
+            self.request(command, arguments)
+                .iter()
+                .filter(|message| message["type"] == "event")
+                .map(|message| match message["body"]["reason"].as_str() {
+                    Some(reason) => format!("{}: {}", message["event"].as_str().unwrap(), reason),
+                    None => message["event"].as_str().unwrap().to_string(),
+                })
+                .collect()
+        }
+
+        fn line(&mut self) -> u64 {
+            let trace = self.request("stackTrace", json!({ "threadId": THREAD }));
+            trace[0]["body"]["stackFrames"][0]["line"].as_u64().unwrap()
+        }
+    }
+
+    fn source(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dap-{}-{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_source_breakpoints_and_stepping() {
+        let path = source("loop.s", PROGRAM);
+        let mut client = Client::launch(&path);
+
+        let reply = client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }, { "line": 100 }] }),
+        );
+        let breakpoints = &reply[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0], json!({ "verified": true, "line": 4 }));
+        assert_eq!(breakpoints[1]["verified"], json!(false));
+
+        assert_eq!(
+            client.events("configurationDone", json!({})),
+            ["stopped: entry"]
+        );
+        assert_eq!(client.line(), 1);
+        assert_eq!(client.events("stepIn", json!({})), ["stopped: step"]);
+        assert_eq!(client.line(), 2);
+        assert_eq!(
+            client.events("continue", json!({})),
+            ["stopped: breakpoint"]
+        );
+        assert_eq!(client.line(), 4);
+
+        assert_eq!(
+            client.events("setBreakpoints", json!({ "breakpoints": [] })),
+            Vec::<String>::new()
+        );
+        client.request("stepIn", json!({}));
+        client.request("stepIn", json!({}));
+        client.request("stepIn", json!({}));
+        client.request("stepIn", json!({}));
+        assert_eq!(client.line(), 8);
+        // `next` only steps over calls; a backward jump moves one instruction.
+        assert_eq!(client.events("next", json!({})), ["stopped: step"]);
+        assert_eq!(client.line(), 4);
+        assert_eq!(
+            client.events("continue", json!({})),
+            ["exited", "terminated"]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_step_out() {
+        let path = source(
+            "call.s",
+            "\
+    call outer
+    hlt
+outer:
+    push r0
+    call inner
+    pop r0
+    ret
+inner:
+    ret
+",
+        );
+        let mut client = Client::launch(&path);
+        client.request("configurationDone", json!({}));
+        client.request("stepIn", json!({}));
+        client.request("stepIn", json!({}));
+        assert_eq!(client.line(), 5);
+        assert_eq!(client.events("next", json!({})), ["stopped: step"]);
+        assert_eq!(client.line(), 6);
+        // `pop r0` raises sp first, but only `ret` leaves `outer`.
+        assert_eq!(client.events("stepOut", json!({})), ["stopped: step"]);
+        assert_eq!(client.line(), 2);
+        assert_eq!(
+            client.events("stepOut", json!({})),
+            ["exited", "terminated"]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_variables() {
+        let path = source("vars.s", PROGRAM);
+        let mut client = Client::launch(&path);
+        client.request("configurationDone", json!({}));
+        client.request("setBreakpoints", json!({ "breakpoints": [{ "line": 6 }] }));
+        client.request("continue", json!({}));
+
+        let reply = client.request("variables", json!({ "variablesReference": REGISTERS }));
+        let variables = reply[0]["body"]["variables"].as_array().unwrap();
+        assert_eq!(variables.len(), 11);
+        assert_eq!(
+            variables[2],
+            json!({ "name": "r2", "value": "1 (0x0001)", "variablesReference": 0 })
+        );
+        assert_eq!(variables[9]["value"], json!("----"));
+        assert_eq!(variables[10]["value"], json!("256 (0x0100)"));
+
+        let reply = client.request(
+            "variables",
+            json!({ "variablesReference": RAM, "start": 64, "count": 2 }),
+        );
+        let variables = reply[0]["body"]["variables"].as_array().unwrap();
+        assert_eq!(variables[0]["name"], json!("[0x40]"));
+        assert_eq!(variables[0]["value"], json!("1 (0x0001)"));
+
+        let reply = client.request(
+            "variables",
+            json!({ "variablesReference": RAM, "start": 255, "count": u64::MAX }),
+        );
+        assert_eq!(reply[0]["body"]["variables"].as_array().unwrap().len(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_image_uses_addresses() {
+        let path = source("prog.hexw", "4003\n7040\n7800\n");
+        let mut client = Client::launch(&path);
+        let reply = client.request("setBreakpoints", json!({ "breakpoints": [{ "line": 3 }] }));
+        assert_eq!(reply[0]["body"]["breakpoints"][0]["line"], json!(3));
+
+        client.request("configurationDone", json!({}));
+        assert_eq!(
+            client.events("continue", json!({})),
+            ["stopped: breakpoint"]
+        );
+        let trace = client.request("stackTrace", json!({}));
+        let frame = &trace[0]["body"]["stackFrames"][0];
+        assert_eq!(frame["name"], json!("hlt"));
+        assert_eq!(frame["source"]["sourceReference"], json!(LISTING));
+
+        let reply = client.request("source", json!({ "sourceReference": LISTING }));
+        assert!(reply[0]["body"]["content"]
+            .as_str()
+            .unwrap()
+            .contains("st r0, 64"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut client = Client {
+            server: DapServer::new(Vec::new()),
+            seq: 0,
+        };
+        let reply = client.request("stackTrace", json!({}));
+        assert_eq!(reply[0]["success"], json!(false));
+        let reply = client.request("launch", json!({ "program": "/nonexistent.s" }));
+        assert_eq!(reply[0]["success"], json!(false));
+        let reply = client.request("evaluate", json!({}));
+        assert_eq!(reply[0]["message"], json!("unsupported request `evaluate`"));
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, &json!({ "seq": 1 })).unwrap();
+        assert_eq!(out, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+        let message = read_message(&mut Cursor::new(out)).unwrap();
+        assert_eq!(message, Some(json!({ "seq": 1 })));
+
+        let huge = format!("Content-Length: {}\r\n\r\n", usize::MAX);
+        let err = read_message(&mut Cursor::new(huge)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,526 @@
+//! The commands behind the `rdb` REPL, kept apart from the terminal so they can
+//! be driven from tests.
+
+use crate::asm::Program;
+use crate::cpu_emu::{CpuEmu, Flags, Rom, Slot, StopReason, Watchpoint};
+use crate::disasm::Disassembled;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+pub const HELP: &str = "\
+step [N]             execute N instructions (default 1)        alias: s
+next                 step, running a call until it returns     alias: n
+continue             run until a breakpoint, watchpoint or hlt alias: c
+break [LOC]          set a breakpoint at LOC, or list them     alias: b
+delete LOC           remove the breakpoint at LOC              alias: d
+watch ram[A] | rN    stop after a write to ram[A] or a change of rN
+rwatch ram[A]        stop after a read of ram[A]
+unwatch ram[A] | rN  remove watchpoints on ram[A] or rN
+regs                 show pc, sp, flags and registers          alias: r
+x/N ram|rom ADDR     examine N words starting at ADDR
+set TARGET = VALUE   TARGET is rN, pc, sp, flags (bits VNCZ) or ram[A]
+disas [N]            disassemble N instructions around the pc
+history              list previous commands; !! and !N repeat them
+quit                 leave the debugger                        alias: q
+
+LOC, ADDR and VALUE are decimal, 0x hex, 0b binary or a label.
+An empty line repeats the last command. next gives up after 1000000
+instructions, in case a call never returns.";
+
+/// Instructions `next` runs at most inside a call before handing control back.
+pub const NEXT_BUDGET: usize = 1_000_000;
+
+/// What the front end should do after a command.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reply {
+    Output(String),
+    Quit,
+}
+
+pub struct Debugger {
+    cpu: CpuEmu,
+    labels: BTreeMap<String, usize>,
+    history: Vec<String>,
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Self {
+        Self {
+            cpu: CpuEmu::new(Rom::new(program.words)),
+            labels: program.labels,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn cpu(&self) -> &CpuEmu {
+        &self.cpu
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Runs one line of input, expanding history references first.
+    pub fn execute(&mut self, line: &str) -> Result<Reply, String> {
+        let line = line.trim();
+        let command = match line {
+            "" => match self.history.last() {
+                Some(last) => last.clone(),
+                None => return Ok(Reply::Output(String::new())),
+            },
+            "!!" => self.history.last().cloned().ok_or("no previous command")?,
+            _ => match line.strip_prefix('!') {
+                Some(index) => index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| self.history.get(index.checked_sub(1)?))
+                    .cloned()
+                    .ok_or_else(|| format!("no command `{}` in history", line))?,
+                None => line.to_string(),
+            },
+        };
+        if !line.is_empty() {
+            self.history.push(command.clone());
+        }
+
+        self.command(&command)
+    }
+
+    fn command(&mut self, command: &str) -> Result<Reply, String> {
+        let (name, args) = match command.find(|c: char| c.is_whitespace() || c == '/') {
+            Some(at) => command.split_at(at),
+            None => (command, ""),
+        };
+        let args = args.trim();
+
+        let output = match name {
+            "help" | "h" => HELP.to_string(),
+            "quit" | "q" => return Ok(Reply::Quit),
+            "step" | "s" => {
+                let count = if args.is_empty() {
+                    1
+                } else {
+                    self.value(args)?
+                };
+                let reason = self.cpu.run_for(count);
+                self.stopped(reason)
+            }
+            "next" | "n" => match self.cpu.step_over(NEXT_BUDGET) {
+                Some(reason) => self.stopped(reason),
+                None => {
+                    let status = format!("next: gave up after {} instructions", NEXT_BUDGET);
+                    format!("{}\n{}", status, self.current())
+                }
+            },
+            "continue" | "c" => {
+                let reason = self.cpu.run_until(|_| false);
+                self.stopped(reason)
+            }
+            "break" | "b" if args.is_empty() => self.breakpoints(),
+            "break" | "b" => {
+                let addr = self.value(args)?;
+                self.cpu.add_breakpoint(addr);
+                format!("breakpoint at {}", addr)
+            }
+            "delete" | "d" => {
+                let addr = self.value(args)?;
+                if !self.cpu.remove_breakpoint(addr) {
+                    return Err(format!("no breakpoint at {}", addr));
+                }
+                format!("deleted breakpoint at {}", addr)
+            }
+            "watch" => {
+                let watchpoint = match self.target(args)? {
+                    Target::Register(slot) => Watchpoint::Register(slot),
+                    Target::Ram(addr) => Watchpoint::Write(addr),
+                    _ => return Err("can only watch ram[A] or a register".to_string()),
+                };
+                self.cpu.add_watchpoint(watchpoint);
+                format!("watchpoint: {}", watchpoint)
+            }
+            "rwatch" => match self.target(args)? {
+                Target::Ram(addr) => {
+                    self.cpu.add_watchpoint(Watchpoint::Read(addr));
+                    format!("watchpoint: {}", Watchpoint::Read(addr))
+                }
+                _ => return Err("can only rwatch ram[A]".to_string()),
+            },
+            "unwatch" => {
+                let removed = match self.target(args)? {
+                    Target::Register(slot) => {
+                        self.cpu.remove_watchpoint(Watchpoint::Register(slot))
+                    }
+                    Target::Ram(addr) => {
+                        let read = self.cpu.remove_watchpoint(Watchpoint::Read(addr));
+                        self.cpu.remove_watchpoint(Watchpoint::Write(addr)) || read
+                    }
+                    _ => false,
+                };
+                if !removed {
+                    return Err(format!("no watchpoint on {}", args));
+                }
+                format!("removed watchpoints on {}", args)
+            }
+            "regs" | "r" => self.registers(),
+            "x" => self.examine(args)?,
+            "set" => self.set(args)?,
+            "disas" => {
+                let count = if args.is_empty() {
+                    9
+                } else {
+                    self.value(args)?
+                };
+                self.disassemble(count)
+            }
+            "history" => self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(index, command)| format!("{:>4}  {}", index + 1, command))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => return Err(format!("unknown command `{}`, try `help`", name)),
+        };
+
+        Ok(Reply::Output(output))
+    }
+
+    fn stopped(&self, reason: StopReason) -> String {
+        let status = match reason {
+            StopReason::Halted => "halted".to_string(),
+            StopReason::Breakpoint(addr) if self.cpu.breakpoints().any(|at| at == addr) => {
+                format!("breakpoint at {}", addr)
+            }
+            StopReason::Breakpoint(_) | StopReason::BudgetExhausted => return self.current(),
+            StopReason::Watchpoint(hit) => hit.to_string(),
+            StopReason::Fault(err) => format!("fault: {}", err),
+        };
+
+        if self.cpu.is_halted() {
+            status
+        } else {
+            format!("{}\n{}", status, self.current())
+        }
+    }
+
+    /// The instruction at the PC, or a note that it lies outside the ROM.
+    fn current(&self) -> String {
+        let pc = self.cpu.pc();
+        match self.cpu.rom().read(pc) {
+            Ok(_) => self.line(pc),
+            Err(err) => format!("pc {}: {}", pc, err),
+        }
+    }
+
+    fn line(&self, addr: usize) -> String {
+        let word = self.cpu.rom().words()[addr];
+        let marker = if addr == self.cpu.pc() { "=>" } else { "  " };
+        let stop = if self.cpu.breakpoints().any(|at| at == addr) {
+            '*'
+        } else {
+            ' '
+        };
+        let mut line = format!(
+            "{}{} {:>3}  {}",
+            marker,
+            stop,
+            addr,
+            Disassembled::new(addr, word).source()
+        );
+
+        let labels: Vec<&str> = self
+            .labels
+            .iter()
+            .filter(|&(_, &at)| at == addr)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !labels.is_empty() {
+            let _ = write!(line, "  ; {}", labels.join(", "));
+        }
+        line
+    }
+
+    fn disassemble(&self, count: usize) -> String {
+        let len = self.cpu.rom().words().len();
+        let start = self.cpu.pc().saturating_sub(count / 2).min(len);
+        let end = (start + count).min(len);
+        (start..end)
+            .map(|addr| self.line(addr))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn breakpoints(&self) -> String {
+        let mut lines: Vec<String> = self
+            .cpu
+            .breakpoints()
+            .map(|addr| format!("breakpoint at {}", addr))
+            .collect();
+        lines.extend(
+            self.cpu
+                .watchpoints()
+                .iter()
+                .map(|watchpoint| format!("watchpoint: {}", watchpoint)),
+        );
+        if lines.is_empty() {
+            return "no breakpoints or watchpoints".to_string();
+        }
+        lines.join("\n")
+    }
+
+    fn registers(&self) -> String {
+        let mut text = format!(
+            "pc: {:>3}  sp: {:>3}  flags: {}",
+            self.cpu.pc(),
+            self.cpu.sp(),
+            self.cpu.flags()
+        );
+        if self.cpu.is_halted() {
+            text.push_str("  halted");
+        }
+        for row in Slot::ALL.chunks(4) {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|&slot| {
+                    let value = self.cpu.register(slot);
+                    format!("{}: {:#06x} ({:>5})", slot, value, value)
+                })
+                .collect();
+            let _ = write!(text, "\n{}", cells.join("  "));
+        }
+        text
+    }
+
+    /// `x/16 ram 0x40`: eight words per row, like the `--dump` output.
+    fn examine(&self, args: &str) -> Result<String, String> {
+        let (count, rest) = match args.strip_prefix('/') {
+            Some(args) => {
+                let (count, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                (self.value(count)?, rest.trim())
+            }
+            None => (1, args),
+        };
+        let (space, addr) = rest
+            .split_once(char::is_whitespace)
+            .ok_or("usage: x/N ram|rom ADDR")?;
+        let addr = self.value(addr.trim())?;
+        let end = addr
+            .checked_add(count)
+            .ok_or_else(|| format!("{} {}+{} is out of bounds", space, addr, count))?;
+
+        let words = match space {
+            "ram" => self.cpu.ram(addr..end).map_err(|err| err.to_string())?,
+            "rom" => self
+                .cpu
+                .rom()
+                .words()
+                .get(addr..end)
+                .ok_or_else(|| format!("rom {}..{} is out of bounds", addr, end))?,
+            _ => return Err(format!("unknown address space `{}`", space)),
+        };
+
+        Ok(words
+            .chunks(8)
+            .enumerate()
+            .map(|(row, words)| {
+                let words: Vec<String> = words.iter().map(|word| format!("{:04x}", word)).collect();
+                format!("{:#04x}: {}", addr + row * 8, words.join(" "))
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    fn set(&mut self, args: &str) -> Result<String, String> {
+        let (target, value) = args.split_once('=').ok_or("usage: set TARGET = VALUE")?;
+        let (target, value) = (target.trim(), value.trim());
+        let data = u16::try_from(self.value(value)?)
+            .map_err(|_| format!("`{}` does not fit in 16 bits", value))?;
+
+        match self.target(target)? {
+            Target::Register(slot) => self.cpu.set_register(slot, data),
+            Target::Pc => self.cpu.set_pc(data as usize),
+            Target::Sp => self.cpu.set_sp(data as usize),
+            Target::Flags => self.cpu.set_flags(Flags::from_bits(data)),
+            Target::Ram(addr) => self
+                .cpu
+                .write_ram(addr, &[data])
+                .map_err(|err| err.to_string())?,
+        }
+        Ok(format!("{} = {}", target, data))
+    }
+
+    fn target(&self, text: &str) -> Result<Target, String> {
+        if let Some(addr) = text
+            .strip_prefix("ram[")
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            return Ok(Target::Ram(self.value(addr.trim())?));
+        }
+        match text {
+            "pc" => return Ok(Target::Pc),
+            "sp" => return Ok(Target::Sp),
+            "flags" => return Ok(Target::Flags),
+            _ => {}
+        }
+        text.strip_prefix('r')
+            .and_then(|index| index.parse::<u16>().ok())
+            .and_then(|index| Slot::try_from(index).ok())
+            .map(Target::Register)
+            .ok_or_else(|| format!("expected rN, pc, sp, flags or ram[A], found `{}`", text))
+    }
+
+    /// A number in decimal, `0x` hex or `0b` binary, or a label.
+    fn value(&self, text: &str) -> Result<usize, String> {
+        if let Some(&addr) = self.labels.get(text) {
+            return Ok(addr);
+        }
+        let digits = text.to_ascii_lowercase().replace('_', "");
+        let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+            usize::from_str_radix(hex, 16)
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            usize::from_str_radix(bin, 2)
+        } else {
+            digits.parse()
+        };
+        parsed.map_err(|_| format!("expected a number or label, found `{}`", text))
+    }
+}
+
+enum Target {
+    Register(Slot),
+    Pc,
+    Sp,
+    Flags,
+    Ram(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_program;
+
+    const PROGRAM: &str = "
+        ldl r0, 1
+        ldl r1, 3
+loop:   add r2, r0
+        st r2, 64
+        cmp r1, r2
+        je done
+        jmp loop
+done:   hlt
+";
+
+    fn debugger() -> Debugger {
+        Debugger::new(assemble_program(PROGRAM).unwrap())
+    }
+
+    fn output(debugger: &mut Debugger, line: &str) -> String {
+        match debugger.execute(line) {
+            Ok(Reply::Output(text)) => text,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_step_and_break() {
+        let mut dbg = debugger();
+        assert_eq!(output(&mut dbg, "step"), "=>    1  ldl r1, 3");
+        assert_eq!(output(&mut dbg, "break loop"), "breakpoint at 2");
+        assert_eq!(
+            output(&mut dbg, "c"),
+            "breakpoint at 2\n=>*   2  add r2, r0  ; loop"
+        );
+        assert_eq!(
+            output(&mut dbg, ""),
+            "breakpoint at 2\n=>*   2  add r2, r0  ; loop"
+        );
+        assert_eq!(dbg.cpu().register(Slot::Reg2), 1);
+
+        output(&mut dbg, "delete 2");
+        assert_eq!(output(&mut dbg, "continue"), "halted");
+        assert_eq!(dbg.cpu().register(Slot::Reg2), 3);
+    }
+
+    #[test]
+    fn test_next_steps_over_jump() {
+        let mut dbg = debugger();
+        output(&mut dbg, "step 6");
+        assert_eq!(dbg.cpu().pc(), 6);
+        assert_eq!(output(&mut dbg, "next"), "=>    2  add r2, r0  ; loop");
+    }
+
+    #[test]
+    fn test_next_over_call() {
+        let source = "call twice\nhlt\ntwice: call once\nonce: add r0, r1\nret";
+        let mut dbg = Debugger::new(assemble_program(source).unwrap());
+        output(&mut dbg, "set r1 = 1");
+        assert_eq!(output(&mut dbg, "next"), "=>    1  hlt");
+        assert_eq!(dbg.cpu().register(Slot::Reg0), 2);
+
+        let mut dbg = Debugger::new(assemble_program("call spin\nhlt\nspin: jmp spin").unwrap());
+        assert_eq!(
+            output(&mut dbg, "next"),
+            "next: gave up after 1000000 instructions\n=>    2  jmp 2  ; spin"
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut dbg = debugger();
+        output(&mut dbg, "watch ram[0x40]");
+        assert_eq!(
+            output(&mut dbg, "c"),
+            "watchpoint write ram[64] at pc 3: 0 -> 1\n=>    4  cmp r1, r2"
+        );
+        output(&mut dbg, "unwatch ram[64]");
+        output(&mut dbg, "watch r1");
+        assert_eq!(output(&mut dbg, "break"), "watchpoint: change r1");
+        assert_eq!(output(&mut dbg, "c"), "halted");
+    }
+
+    #[test]
+    fn test_set_and_examine() {
+        let mut dbg = debugger();
+        assert_eq!(output(&mut dbg, "set r3 = 10"), "r3 = 10");
+        assert_eq!(output(&mut dbg, "set ram[65] = 0xbeef"), "ram[65] = 48879");
+        assert_eq!(
+            output(&mut dbg, "x/16 ram 0x40"),
+            "0x40: 0000 beef 0000 0000 0000 0000 0000 0000\n\
+             0x48: 0000 0000 0000 0000 0000 0000 0000 0000"
+        );
+        assert_eq!(output(&mut dbg, "x rom 7"), "0x07: 7800");
+        assert!(output(&mut dbg, "regs").contains("r3: 0x000a (   10)"));
+        assert!(dbg.execute("set r9 = 1").is_err());
+        assert!(dbg.execute("x/2 ram 255").is_err());
+        assert!(dbg.execute("x/16 ram 0xffffffffffffffff").is_err());
+    }
+
+    #[test]
+    fn test_disas() {
+        let mut dbg = debugger();
+        output(&mut dbg, "step 2");
+        output(&mut dbg, "b 3");
+        assert_eq!(
+            output(&mut dbg, "disas 3"),
+            "      1  ldl r1, 3\n=>    2  add r2, r0  ; loop\n  *   3  st r2, 64"
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut dbg = debugger();
+        output(&mut dbg, "step");
+        output(&mut dbg, "regs");
+        output(&mut dbg, "!1");
+        assert_eq!(dbg.cpu().pc(), 2);
+        output(&mut dbg, "!!");
+        assert_eq!(dbg.cpu().pc(), 3);
+        assert_eq!(
+            output(&mut dbg, "history"),
+            "   1  step\n   2  regs\n   3  step\n   4  step\n   5  history"
+        );
+        assert!(dbg.execute("!42").is_err());
+        assert_eq!(dbg.execute("quit"), Ok(Reply::Quit));
+    }
+}
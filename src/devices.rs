@@ -0,0 +1,16 @@
+//! Memory-mapped peripherals for `AddressDecoder::attach`, and where the CLI maps them.
+
+pub mod framebuffer;
+pub mod timer;
+pub mod uart;
+
+pub use framebuffer::{Frame, Framebuffer};
+pub use timer::Timer;
+pub use uart::{Background, Uart};
+
+/// Data address of the framebuffer's pixels and frame-done register.
+pub const FRAMEBUFFER_BASE: usize = 0xfe00;
+/// Data address of the UART's registers.
+pub const UART_BASE: usize = 0xff00;
+/// Data address of the timer's registers.
+pub const TIMER_BASE: usize = 0xff10;
@@ -0,0 +1,199 @@
+use crate::cpu_emu::Device;
+use std::fmt;
+
+/// Pixels per row.
+pub const WIDTH: usize = 32;
+/// Rows.
+pub const HEIGHT: usize = 32;
+/// Words of pixel data: each row is `WIDTH / 16` words, leftmost pixel in bit 15.
+pub const WORDS: usize = WIDTH * HEIGHT / 16;
+/// Offset of the frame-done register, right after the pixels. Writing any
+/// value presents the frame; reading gives the number of frames presented.
+pub const FRAME_DONE: usize = WORDS;
+/// Words of address space the device takes.
+pub const LEN: usize = WORDS + 1;
+
+/// The pixels of one presented frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Counts from 1.
+    pub number: u64,
+    pub words: [u16; WORDS],
+}
+
+impl Frame {
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let word = self.words[(y * WIDTH + x) / 16];
+        word & (0x8000 >> (x % 16)) != 0
+    }
+
+    /// A binary PPM (`P6`) image, lit pixels white on black.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let level = if self.pixel(x, y) { 255 } else { 0 };
+                ppm.extend_from_slice(&[level; 3]);
+            }
+        }
+        ppm
+    }
+}
+
+impl fmt::Display for Frame {
+    /// Two rows per line of text with half-block characters, so the frame keeps
+    /// its aspect ratio in a terminal.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in (0..HEIGHT).step_by(2) {
+            let line: String = (0..WIDTH)
+                .map(|x| match (self.pixel(x, y), self.pixel(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect();
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+type FrameHandler = Box<dyn FnMut(&Frame)>;
+
+/// A monochrome framebuffer. Programs draw into the pixel words and write
+/// `FRAME_DONE` when the picture is complete.
+pub struct Framebuffer {
+    words: [u16; WORDS],
+    frames: u64,
+    on_frame: Option<FrameHandler>,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            words: [0; WORDS],
+            frames: 0,
+            on_frame: None,
+        }
+    }
+
+    /// Calls `on_frame` with every frame the program presents.
+    pub fn on_frame<F: FnMut(&Frame) + 'static>(&mut self, on_frame: F) {
+        self.on_frame = Some(Box::new(on_frame));
+    }
+
+    /// Number of frames presented so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The pixels as they are now, presented or not.
+    pub fn frame(&self) -> Frame {
+        Frame {
+            number: self.frames,
+            words: self.words,
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: usize) -> u16 {
+        match offset {
+            FRAME_DONE => self.frames as u16,
+            offset => self.words.get(offset).copied().unwrap_or(0),
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: u16) {
+        if offset != FRAME_DONE {
+            if let Some(word) = self.words.get_mut(offset) {
+                *word = data;
+            }
+            return;
+        }
+
+        self.frames += 1;
+        let frame = self.frame();
+        if let Some(on_frame) = self.on_frame.as_mut() {
+            on_frame(&frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cpu_emu::{CpuEmu, Slot};
+    use crate::devices::FRAMEBUFFER_BASE;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_pixels() {
+        let mut words = [0; WORDS];
+        words[0] = 0x8001; // (0, 0) and (15, 0)
+        words[3] = 0x0001; // (31, 1)
+        let frame = Frame { number: 1, words };
+
+        assert!(frame.pixel(0, 0) && frame.pixel(15, 0) && frame.pixel(31, 1));
+        assert!(!frame.pixel(16, 0) && !frame.pixel(0, 1));
+        let first = format!("▀{}▀{}▄\n\n", " ".repeat(14), " ".repeat(15));
+        assert!(frame.to_string().starts_with(&first));
+    }
+
+    #[test]
+    fn test_ppm() {
+        let mut words = [0; WORDS];
+        words[0] = 0x4000; // (1, 0)
+        let ppm = Frame { number: 1, words }.to_ppm();
+
+        let header = b"P6\n32 32\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + WIDTH * HEIGHT * 3);
+        assert_eq!(
+            &ppm[header.len()..header.len() + 6],
+            &[0, 0, 0, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_frame_done() {
+        // Draws the top-left pixel and presents, then adds the one right of it.
+        let source = "
+                ldh r0, 0xfe        ; r0 = first pixel word
+                ldh r1, 0x80
+                st r1, [r0]
+                ldl r2, 64          ; r2 = FRAME_DONE
+                ldh r2, 0xfe
+                st r2, [r2]
+                ldh r1, 0xc0
+                st r1, [r0]
+                st r2, [r2]
+                ld r3, [r2]
+                hlt
+        ";
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let mut framebuffer = Framebuffer::new();
+        let seen = frames.clone();
+        framebuffer.on_frame(move |frame| seen.borrow_mut().push(frame.clone()));
+
+        let mut cpu = CpuEmu::new(assemble(source).unwrap());
+        cpu.bus_mut()
+            .attach(FRAMEBUFFER_BASE..FRAMEBUFFER_BASE + LEN, framebuffer);
+        cpu.run().unwrap();
+
+        let frames = frames.borrow();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].number, frames[0].words[0]), (1, 0x8000));
+        assert_eq!((frames[1].number, frames[1].words[0]), (2, 0xc000));
+        assert_eq!(cpu.register(Slot::Reg3), 2);
+    }
+}
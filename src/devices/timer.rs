@@ -0,0 +1,188 @@
+use crate::cpu_emu::{Device, LINES};
+
+/// Offset of the reload value register.
+pub const LOAD: usize = 0;
+/// Offset of the current count, which reads down to 0; writes are ignored.
+pub const COUNT: usize = 1;
+/// Offset of the control register.
+pub const CONTROL: usize = 2;
+/// Offset of the status register.
+pub const STATUS: usize = 3;
+/// Words of address space the registers take.
+pub const LEN: usize = 4;
+
+/// `CONTROL` bit: count down. Writing it set reloads `COUNT` from `LOAD`.
+pub const ENABLE: u16 = 0b001;
+/// `CONTROL` bit: reload and keep counting on expiry instead of stopping.
+pub const PERIODIC: u16 = 0b010;
+/// `CONTROL` bit: raise the timer's interrupt line on expiry.
+pub const IRQ_ENABLE: u16 = 0b100;
+
+/// `STATUS` bit: the count reached 0. Sticky; write the bit back to clear it.
+pub const EXPIRED: u16 = 0b001;
+
+/// A down-counter clocked by emulated cycles.
+///
+/// A one-shot timer clears `ENABLE` when it expires; a periodic one reloads
+/// and carries the cycles past expiry into the next period, so periods do not
+/// drift. A `LOAD` of 0 expires once and stops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timer {
+    line: usize,
+    load: u16,
+    count: u16,
+    control: u16,
+    status: u16,
+}
+
+impl Timer {
+    /// A stopped timer that raises interrupt `line` when enabled to.
+    pub fn new(line: usize) -> Self {
+        assert!(line < LINES, "interrupt line {} out of range", line);
+        Self {
+            line,
+            load: 0,
+            count: 0,
+            control: 0,
+            status: 0,
+        }
+    }
+
+    /// Counts down by `cycles`; returns whether the count reached 0 at least once.
+    fn advance(&mut self, mut cycles: u64) -> bool {
+        let mut expired = false;
+        while self.control & ENABLE != 0 && cycles >= u64::from(self.count) {
+            cycles -= u64::from(self.count);
+            expired = true;
+            if self.control & PERIODIC != 0 && self.load != 0 {
+                self.count = self.load;
+            } else {
+                self.count = 0;
+                self.control &= !ENABLE;
+            }
+        }
+        if self.control & ENABLE != 0 {
+            self.count -= cycles as u16;
+        }
+        expired
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset: usize) -> u16 {
+        match offset {
+            LOAD => self.load,
+            COUNT => self.count,
+            CONTROL => self.control,
+            STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: u16) {
+        match offset {
+            LOAD => self.load = data,
+            CONTROL => {
+                self.control = data;
+                if data & ENABLE != 0 {
+                    self.count = self.load;
+                }
+            }
+            STATUS => self.status &= !data,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) -> Option<usize> {
+        if !self.advance(cycles) {
+            return None;
+        }
+        self.status |= EXPIRED;
+        (self.control & IRQ_ENABLE != 0).then_some(self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cpu_emu::{CpuEmu, Slot, StopReason, Timing};
+    use crate::devices::TIMER_BASE;
+
+    #[test]
+    fn test_one_shot() {
+        let mut timer = Timer::new(0);
+        timer.write(LOAD, 3);
+        timer.write(CONTROL, ENABLE | IRQ_ENABLE);
+
+        assert_eq!(timer.tick(2), None);
+        assert_eq!(timer.read(COUNT), 1);
+        assert_eq!(timer.tick(1), Some(0));
+        assert_eq!(timer.read(STATUS), EXPIRED);
+        assert_eq!(timer.read(CONTROL), IRQ_ENABLE);
+        assert_eq!(timer.tick(10), None);
+
+        timer.write(STATUS, EXPIRED);
+        assert_eq!(timer.read(STATUS), 0);
+    }
+
+    #[test]
+    fn test_periodic() {
+        let mut timer = Timer::new(2);
+        timer.write(LOAD, 4);
+        timer.write(CONTROL, ENABLE | PERIODIC);
+
+        // Expires without raising its line, and keeps the overshoot.
+        assert_eq!(timer.tick(5), None);
+        assert_eq!(timer.read(STATUS), EXPIRED);
+        assert_eq!(timer.read(COUNT), 3);
+
+        timer.write(CONTROL, ENABLE | PERIODIC | IRQ_ENABLE);
+        assert_eq!(timer.tick(9), Some(2));
+        assert_eq!(timer.read(COUNT), 3);
+    }
+
+    #[test]
+    fn test_interrupt_driven_count() {
+        // Three periodic interrupts of 10 cycles each bump r2; the main loop
+        // halts once it sees 3. One cycle per instruction keeps the count simple.
+        let source = "
+                ldl r0, 0xe0        ; vector table at ram[0xe0], line 0 at 0xe1
+                ldl r1, 1
+                add r0, r1
+                ldl r1, handler
+                st r1, [r0]
+                ldh r0, 0xff        ; r0 = timer LOAD
+                ldl r0, 0x10
+                ldl r1, 10
+                st r1, [r0]
+                ldl r1, 2
+                add r0, r1          ; r0 = CONTROL
+                ldl r1, 7           ; ENABLE | PERIODIC | IRQ_ENABLE
+                st r1, [r0]
+                ldl r1, 1
+                add r0, r1          ; r0 = STATUS
+                ldl r3, 3
+                ei
+            wait:
+                cmp r2, r3
+                jne wait
+                hlt
+            handler:
+                add r2, r1
+                st r1, [r0]         ; clear EXPIRED
+                reti
+        ";
+        let mut cpu = CpuEmu::new(assemble(source).unwrap());
+        cpu.set_timing(Timing::uniform());
+        cpu.set_vectors(Some(0xe0)).unwrap();
+        cpu.bus_mut()
+            .attach(TIMER_BASE..TIMER_BASE + LEN, Timer::new(0));
+
+        assert_eq!(cpu.run_for(100), StopReason::Halted);
+        assert_eq!(cpu.register(Slot::Reg2), 3);
+        // Enabled by the 13th instruction, the timer fires after the 22nd, 32nd
+        // and 42nd; the last handler returns at 45 and the loop halts at 49.
+        assert_eq!(cpu.executed(), 49);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::cpu_emu::Device;
+use std::io::{self, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Offset of the transmit/receive data register.
+pub const DATA: usize = 0;
+/// Offset of the status register.
+pub const STATUS: usize = 1;
+/// Words of address space the registers take.
+pub const LEN: usize = 2;
+
+/// `STATUS` bit: a byte is waiting in `DATA`.
+pub const RX_READY: u16 = 0b001;
+/// `STATUS` bit: `DATA` accepts a byte; always set.
+pub const TX_READY: u16 = 0b010;
+/// `STATUS` bit: the input is exhausted and nothing is waiting.
+pub const RX_EOF: u16 = 0b100;
+
+/// A serial console: bytes written to `DATA` go to `output`, bytes from
+/// `input` are read back one at a time.
+///
+/// Only the low byte of `DATA` is used. Reading `DATA` with nothing received
+/// yields 0; check `STATUS` first. An `input` that fails with `WouldBlock`,
+/// like `Background`, has nothing to receive yet but may later.
+pub struct Uart<R, W> {
+    input: R,
+    output: W,
+    received: Option<u8>,
+    eof: bool,
+}
+
+impl<R: Read, W: Write> Uart<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            received: None,
+            eof: false,
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    /// Pulls the next input byte in if none is waiting. This blocks until
+    /// `input` has a byte or reports `WouldBlock`.
+    fn receive(&mut self) {
+        if self.received.is_some() || self.eof {
+            return;
+        }
+        let mut byte = [0];
+        match self.input.read(&mut byte) {
+            Ok(1) => self.received = Some(byte[0]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            _ => self.eof = true,
+        }
+    }
+}
+
+/// Reads a blocking source such as stdin on a background thread, so a program
+/// polling `STATUS` keeps running while the host has not typed anything.
+///
+/// `read` returns at most one byte, and fails with `WouldBlock` while none
+/// has arrived.
+pub struct Background {
+    bytes: Receiver<u8>,
+}
+
+impl Background {
+    pub fn spawn<R: Read + Send + 'static>(input: R) -> Self {
+        let (sender, bytes) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in BufReader::new(input).bytes() {
+                match byte {
+                    Ok(byte) if sender.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Self { bytes }
+    }
+}
+
+impl Read for Background {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.bytes.try_recv() {
+            Ok(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            Err(TryRecvError::Empty) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(TryRecvError::Disconnected) => Ok(0),
+        }
+    }
+}
+
+impl<R: Read, W: Write> Device for Uart<R, W> {
+    fn read(&mut self, offset: usize) -> u16 {
+        match offset {
+            DATA => {
+                self.receive();
+                self.received.take().map_or(0, u16::from)
+            }
+            STATUS => {
+                self.receive();
+                let mut status = TX_READY;
+                if self.received.is_some() {
+                    status |= RX_READY;
+                } else if self.eof {
+                    status |= RX_EOF;
+                }
+                status
+            }
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: u16) {
+        if offset == DATA {
+            // A console that went away is not the program's fault.
+            let _ = self.output.write_all(&[data as u8]);
+            let _ = self.output.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cpu_emu::CpuEmu;
+    use crate::devices::UART_BASE;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Console = Uart<&'static [u8], Vec<u8>>;
+
+    fn run(source: &str, input: &'static [u8]) -> Rc<RefCell<Console>> {
+        let uart = Rc::new(RefCell::new(Uart::new(input, Vec::new())));
+        let mut cpu = CpuEmu::new(assemble(source).unwrap());
+        cpu.bus_mut()
+            .attach(UART_BASE..UART_BASE + LEN, uart.clone());
+        cpu.run().unwrap();
+        uart
+    }
+
+    #[test]
+    fn test_print() {
+        let uart = run(
+            "
+                ldh r1, 0xff        ; r1 = UART data register
+                ldl r0, 0x68        ; 'h'
+                st r0, [r1]
+                ldl r0, 0x69        ; 'i'
+                st r0, [r1]
+                hlt
+            ",
+            b"",
+        );
+        assert_eq!(uart.borrow().output(), b"hi");
+    }
+
+    #[test]
+    fn test_echo_upper_case() {
+        // Echoes the input with bit 5 cleared until it runs out.
+        let uart = run(
+            "
+                ldh r1, 0xff        ; r1 = data, r2 = status
+                ldh r2, 0xff
+                ldl r2, 1
+                ldl r3, 1           ; RX_READY
+                ldl r4, 0xdf        ; ~0x20
+            loop:
+                ld r0, [r2]
+                and r0, r3
+                je done
+                ld r0, [r1]
+                and r0, r4
+                st r0, [r1]
+                jmp loop
+            done:
+                hlt
+            ",
+            b"echo",
+        );
+        assert_eq!(uart.borrow().output(), b"ECHO");
+    }
+
+    #[test]
+    fn test_status() {
+        let mut uart = Uart::new(&b"x"[..], Vec::new());
+        assert_eq!(uart.read(STATUS), 0b011);
+        assert_eq!(uart.read(DATA), u16::from(b'x'));
+        assert_eq!(uart.read(STATUS), 0b110);
+        assert_eq!(uart.read(DATA), 0);
+    }
+
+    #[test]
+    fn test_background_does_not_block() {
+        // Hands bytes over one at a time, blocking in between like a terminal.
+        struct Typist(mpsc::Receiver<u8>);
+        impl Read for Typist {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.recv() {
+                    Ok(byte) => {
+                        buf[0] = byte;
+                        Ok(1)
+                    }
+                    Err(_) => Ok(0),
+                }
+            }
+        }
+
+        let (keys, typed) = mpsc::channel();
+        let mut uart = Uart::new(Background::spawn(Typist(typed)), Vec::new());
+        assert_eq!(uart.read(STATUS), TX_READY);
+
+        keys.send(b'x').unwrap();
+        while uart.read(STATUS) == TX_READY {
+            thread::yield_now();
+        }
+        assert_eq!(uart.read(STATUS), TX_READY | RX_READY);
+        assert_eq!(uart.read(DATA), u16::from(b'x'));
+
+        drop(keys);
+        while uart.read(STATUS) == TX_READY {
+            thread::yield_now();
+        }
+        assert_eq!(uart.read(STATUS), TX_READY | RX_EOF);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::cpu_emu::{Field, Format, InstructionRegister, Opcode, Rom};
+use std::fmt;
+
+/// One decoded ROM word.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Disassembled {
+    pub addr: usize,
+    pub word: u16,
+    pub code: Option<Opcode>,
+}
+
+impl Disassembled {
+    pub fn new(addr: usize, word: u16) -> Self {
+        let mut ir = InstructionRegister::new();
+        ir.write(word);
+        Self {
+            addr,
+            word,
+            code: ir.decode().ok(),
+        }
+    }
+
+    /// Bits the decoder ignores for this instruction that are nonetheless set.
+    pub fn ignored_bits(&self) -> u16 {
+        match &self.code {
+            Some(code) => self.word & code.spec().format.dont_care(),
+            None => 0,
+        }
+    }
+
+    /// The word as assembler source: the instruction, or `.word` if it does not decode.
+    pub fn source(&self) -> String {
+        match &self.code {
+            Some(code) => code.to_string(),
+            None => format!(".word {:#06x}", self.word),
+        }
+    }
+
+    fn encoding(&self) -> String {
+        let word = self.word;
+        let field = |field: Field| {
+            format!(
+                "{:0width$b}",
+                field.extract(word),
+                width = field.width as usize
+            )
+        };
+
+        match self.code.map(|code| code.spec().format) {
+            Some(Format::RegData) | Some(Format::RegAddr) | Some(Format::Addr) => {
+                format!(
+                    "{}_{}_{}",
+                    field(Field::CODE),
+                    field(Field::REG_A),
+                    field(Field::IMM)
+                )
+            }
+            _ => format!(
+                "{}_{}_{}_{:05b}",
+                field(Field::CODE),
+                field(Field::REG_A),
+                field(Field::REG_B),
+                word & 0x1f
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Disassembled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>3}: {}  {}",
+            self.addr,
+            self.encoding(),
+            self.source()
+        )?;
+        if self.ignored_bits() != 0 {
+            write!(f, "  ; don't-care bits set: {:#06x}", self.ignored_bits())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn disassemble(rom: &Rom) -> Vec<Disassembled> {
+    rom.words()
+        .iter()
+        .enumerate()
+        .map(|(addr, &word)| Disassembled::new(addr, word))
+        .collect()
+}
+
+/// Renders the whole ROM as `addr: encoding  mnemonic operands` lines.
+pub fn listing(rom: &Rom) -> String {
+    disassemble(rom)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cpu_emu::Slot;
+
+    #[test]
+    fn test_listing() {
+        let rom = Rom::new(vec![
+            0b1000_011_00001010,
+            0b0000_000_001_00000,
+            0b1011_000_00000000,
+            0b1111_000_000_00000,
+        ]);
+        assert_eq!(
+            listing(&rom),
+            "  0: 01000_011_00001010  ldl r3, 10\n\
+            \x20 1: 00000_000_001_00000  mov r0, r1\n\
+            \x20 2: 01011_000_00000000  je 0\n\
+            \x20 3: 01111_000_000_00000  hlt\n"
+        );
+    }
+
+    #[test]
+    fn test_ignored_bits() {
+        let line = Disassembled::new(0, 0b0001_000_001_00011);
+        assert_eq!(line.code, Some(Opcode::Add(Slot::Reg0, Slot::Reg1)));
+        assert_eq!(line.ignored_bits(), 0b00011);
+        assert!(line.to_string().ends_with("; don't-care bits set: 0x0003"));
+
+        assert_eq!(
+            Disassembled::new(0, 0b1100_101_00000001).ignored_bits(),
+            0x0500
+        );
+        assert_eq!(Disassembled::new(0, 0b1000_101_11111111).ignored_bits(), 0);
+    }
+
+    #[test]
+    fn test_unknown_word() {
+        let line = Disassembled::new(7, 0xf800);
+        assert_eq!(line.code, None);
+        assert_eq!(line.source(), ".word 0xf800");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = "
+                ldh r0, 0
+                ldl r0, 1
+                ldl r1, 0xff
+            loop:
+                add r2, r0
+                sub r3, r2
+                and r4, r5
+                or r6, r7
+                sl r1
+                sr r2
+                sra r3
+                st r3, 64
+                ld r4, 64
+                cmp r1, r2
+                je done
+                jmp loop
+            done:
+                hlt
+                .word 0xffff
+        ";
+        let first = listing(&assemble(source).unwrap());
+
+        let sources: Vec<String> = disassemble(&assemble(source).unwrap())
+            .iter()
+            .map(Disassembled::source)
+            .collect();
+        let second = listing(&assemble(&sources.join("\n")).unwrap());
+
+        assert_eq!(first, second);
+    }
+}
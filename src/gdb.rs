@@ -0,0 +1,583 @@
+//! A GDB Remote Serial Protocol stub serving one `CpuEmu` over TCP.
+//!
+//! GDB sees eleven 16-bit registers (`r0`..`r7`, `pc`, `flags`, `sp`) and one byte
+//! address space: ROM word `i` at byte `2 * i`, RAM word `i` at
+//! `RAM_BASE + 2 * i`, both little-endian. The `pc` register holds the byte
+//! address of the next instruction, so `x/i $pc` and `break *0x10` line up;
+//! `sp` likewise holds the byte address of the top of the stack.
+
+use crate::cpu_emu::{CpuEmu, EmuError, Flags, Slot, StopReason, Watchpoint};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/// Byte address of RAM word 0; ROM occupies the bytes below it.
+pub const RAM_BASE: usize = 0x8000;
+
+const PC: usize = 8;
+const FLAGS: usize = 9;
+const SP: usize = 10;
+const REGISTERS: usize = 11;
+
+/// Instructions run between checks for an interrupt from GDB during `c`.
+const CHUNK: usize = 4096;
+/// Longest packet payload accepted, advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x4000;
+
+/// What the session loop should do after a packet.
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Reply(String),
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    cpu: CpuEmu,
+}
+
+impl GdbStub {
+    pub fn new(cpu: CpuEmu) -> Self {
+        Self { cpu }
+    }
+
+    pub fn cpu(&self) -> &CpuEmu {
+        &self.cpu
+    }
+
+    pub fn into_cpu(self) -> CpuEmu {
+        self.cpu
+    }
+
+    /// Serves one GDB session until it detaches, kills or disconnects.
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        while let Some(packet) = read_packet(&mut stream)? {
+            let mut interrupted = || poll_interrupt(&stream);
+            match self.handle(&packet, &mut interrupted) {
+                Action::Reply(reply) => write_packet(&mut stream, &reply)?,
+                Action::Detach => {
+                    write_packet(&mut stream, "OK")?;
+                    break;
+                }
+                Action::Kill => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> Action {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => (0..REGISTERS).map(|n| hex_word(self.register(n))).collect(),
+            "G" => self.write_registers(args),
+            "p" => match parse_hex(args).filter(|&n| n < REGISTERS) {
+                Some(n) => hex_word(self.register(n)),
+                None => error(),
+            },
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" => self.breakpoint(args, true),
+            "z" => self.breakpoint(args, false),
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.cpu.set_pc(addr / 2);
+                }
+                self.resume(command == "s", interrupted)
+            }
+            "H" => "OK".to_string(),
+            "D" => return Action::Detach,
+            "k" => return Action::Kill,
+            _ => self.query(packet),
+        };
+        Action::Reply(reply)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match range.split_once(',') {
+                Some((offset, len)) => match (parse_hex(offset), parse_hex(len)) {
+                    (Some(offset), Some(len)) => {
+                        let xml = target_xml();
+                        let start = offset.min(xml.len());
+                        let end = match start.checked_add(len) {
+                            Some(end) => end.min(xml.len()),
+                            None => return error(),
+                        };
+                        let more = if end < xml.len() { 'm' } else { 'l' };
+                        format!("{}{}", more, &xml[start..end])
+                    }
+                    _ => error(),
+                },
+                None => error(),
+            };
+        }
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn register(&self, n: usize) -> u16 {
+        match n {
+            PC => (self.cpu.pc() * 2) as u16,
+            FLAGS => self.cpu.flags().bits(),
+            SP => (RAM_BASE + self.cpu.sp() * 2) as u16,
+            _ => self.cpu.register(Slot::ALL[n]),
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: u16) {
+        match n {
+            PC => self.cpu.set_pc(value as usize / 2),
+            FLAGS => self.cpu.set_flags(Flags::from_bits(value)),
+            SP => self
+                .cpu
+                .set_sp((value as usize).saturating_sub(RAM_BASE) / 2),
+            _ => self.cpu.set_register(Slot::ALL[n], value),
+        }
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let values: Option<Vec<u16>> = (0..REGISTERS)
+            .map(|n| args.get(n * 4..n * 4 + 4).and_then(parse_word))
+            .collect();
+        match values {
+            Some(values) => {
+                for (n, value) in values.into_iter().enumerate() {
+                    self.set_register(n, value);
+                }
+                "OK".to_string()
+            }
+            None => error(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let parsed = args
+            .split_once('=')
+            .and_then(|(n, value)| Some((parse_hex(n)?, parse_word(value)?)));
+        match parsed {
+            Some((n, value)) if n < REGISTERS => {
+                self.set_register(n, value);
+                "OK".to_string()
+            }
+            _ => error(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let range = args.split_once(',').and_then(|(addr, len)| {
+            let addr = parse_hex(addr)?;
+            Some(addr..addr.checked_add(parse_hex(len)?)?)
+        });
+        let bytes: Option<String> = match range {
+            Some(range) => range
+                .map(|addr| self.read_byte(addr).map(|byte| format!("{:02x}", byte)))
+                .collect(),
+            None => None,
+        };
+        bytes.unwrap_or_else(error)
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let parsed = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = range.split_once(',')?;
+            let bytes = parse_bytes(data)?;
+            (parse_hex(len)? == bytes.len()).then_some((parse_hex(addr)?, bytes))
+        });
+        let (addr, bytes) = match parsed {
+            Some(parsed) => parsed,
+            None => return error(),
+        };
+
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            let written = addr
+                .checked_add(offset)
+                .ok_or(EmuError::RamOutOfBounds { addr })
+                .and_then(|addr| self.write_byte(addr, byte));
+            if written.is_err() {
+                return error();
+            }
+        }
+        "OK".to_string()
+    }
+
+    fn read_byte(&self, addr: usize) -> Option<u8> {
+        let word = if addr < RAM_BASE {
+            self.cpu.rom().read(addr / 2).ok()?
+        } else {
+            let index = (addr - RAM_BASE) / 2;
+            self.cpu.ram(index..index + 1).ok()?[0]
+        };
+        Some(word.to_le_bytes()[addr % 2])
+    }
+
+    fn write_byte(&mut self, addr: usize, byte: u8) -> Result<(), EmuError> {
+        let mut bytes = self
+            .read_byte(addr & !1)
+            .zip(self.read_byte(addr | 1))
+            .map(|(low, high)| [low, high])
+            .ok_or(EmuError::RamOutOfBounds { addr })?;
+        bytes[addr % 2] = byte;
+        let word = u16::from_le_bytes(bytes);
+
+        if addr < RAM_BASE {
+            self.cpu.write_rom(addr / 2, &[word])
+        } else {
+            self.cpu.write_ram((addr - RAM_BASE) / 2, &[word])
+        }
+    }
+
+    /// `Z0`/`z0` on ROM addresses, `Z2`/`Z3` (write/read watchpoints) on RAM.
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = match fields.next().and_then(parse_hex) {
+            Some(addr) => addr,
+            None => return error(),
+        };
+        let ram = |addr: usize| addr.checked_sub(RAM_BASE).map(|offset| offset / 2);
+
+        match (kind, ram(addr)) {
+            (Some("0"), _) if insert => self.cpu.add_breakpoint(addr / 2),
+            (Some("0"), _) => self.cpu.remove_breakpoint(addr / 2),
+            (Some("2"), Some(index)) => self.watch(Watchpoint::Write(index), insert),
+            (Some("3"), Some(index)) => self.watch(Watchpoint::Read(index), insert),
+            (Some("2"), None) | (Some("3"), None) => return error(),
+            _ => return String::new(),
+        };
+        "OK".to_string()
+    }
+
+    fn watch(&mut self, watchpoint: Watchpoint, insert: bool) -> bool {
+        if insert {
+            self.cpu.add_watchpoint(watchpoint)
+        } else {
+            self.cpu.remove_watchpoint(watchpoint)
+        }
+    }
+
+    fn resume(&mut self, step: bool, interrupted: &mut dyn FnMut() -> bool) -> String {
+        let reason = if step {
+            self.cpu.step()
+        } else {
+            loop {
+                match self.cpu.run_for(CHUNK) {
+                    StopReason::BudgetExhausted if interrupted() => return "S02".to_string(),
+                    StopReason::BudgetExhausted => {}
+                    reason => break reason,
+                }
+            }
+        };
+
+        match reason {
+            StopReason::Halted => "W00".to_string(),
+            StopReason::Watchpoint(hit) => match hit.watchpoint {
+                Watchpoint::Write(index) => format!("T05watch:{:x};", RAM_BASE + index * 2),
+                Watchpoint::Read(index) => format!("T05rwatch:{:x};", RAM_BASE + index * 2),
+                Watchpoint::Register(_) => "S05".to_string(),
+            },
+            StopReason::Fault(err) => format!("S{:02x}", signal(&err)),
+            StopReason::Breakpoint(_) | StopReason::BudgetExhausted => "S05".to_string(),
+        }
+    }
+}
+
+/// The POSIX signal GDB shows for a fault.
+fn signal(err: &EmuError) -> u8 {
+    match err {
+        EmuError::IllegalInstruction { .. } | EmuError::InvalidRegister { .. } => 4,
+        EmuError::ArithmeticOverflow { .. } => 8,
+        EmuError::InvalidInterruptLine { .. } => 5,
+        EmuError::RomOutOfBounds { .. }
+        | EmuError::RamOutOfBounds { .. }
+        | EmuError::ReadOnly { .. }
+        | EmuError::StackOverflow { .. }
+        | EmuError::StackUnderflow { .. } => 11,
+    }
+}
+
+fn target_xml() -> String {
+    let mut regs: Vec<String> = Slot::ALL
+        .iter()
+        .map(|slot| format!("<reg name=\"{}\" bitsize=\"16\" type=\"uint16\"/>", slot))
+        .collect();
+    regs.push("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>".to_string());
+    regs.push("<reg name=\"flags\" bitsize=\"16\" type=\"uint16\"/>".to_string());
+    regs.push("<reg name=\"sp\" bitsize=\"16\" type=\"data_ptr\"/>".to_string());
+
+    format!(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <feature name=\"org.rust-risc-emu.core\">{}</feature>\
+         </target>",
+        regs.join("")
+    )
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn hex_word(value: u16) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+/// A register value in target (little-endian) byte order.
+fn parse_word(text: &str) -> Option<u16> {
+    let bytes = <[u8; 2]>::try_from(parse_bytes(text)?).ok()?;
+    Some(u16::from_le_bytes(bytes))
+}
+
+fn checksum(payload: &[u8]) -> u8 {
+    payload.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Reads the next `$payload#xx` packet and acknowledges it, skipping acks and
+/// stray interrupts in between. `None` means the connection closed.
+fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Option<String>> {
+    loop {
+        let mut byte = [0];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut payload = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' || payload.len() == PACKET_SIZE {
+                break;
+            }
+            payload.push(byte[0]);
+        }
+        if byte[0] != b'#' {
+            // Longer than we said we accept: drop it and resync on the next `$`.
+            stream.write_all(b"-")?;
+            continue;
+        }
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum)?;
+
+        let expected = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+        if expected == Some(checksum(&payload)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+fn write_packet<W: Write>(stream: &mut W, payload: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", payload, checksum(payload.as_bytes()))?;
+    stream.flush()
+}
+
+/// Whether GDB sent a Ctrl-C (`0x03`) while the target was running.
+fn poll_interrupt(stream: &TcpStream) -> bool {
+    let mut byte = [0];
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let interrupted = matches!((&*stream).read(&mut byte), Ok(1) if byte[0] == 0x03);
+    let _ = stream.set_nonblocking(false);
+    interrupted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_emu::Rom;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn stub() -> GdbStub {
+        GdbStub::new(CpuEmu::new(Rom::new(vec![
+            0b1000_001_00000011,  // ldl r1, 3
+            0b1110_001_00000010,  // st r1, 2
+            0b1111_000_000_00000, // hlt
+        ])))
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle(packet, &mut || false) {
+            Action::Reply(reply) => reply,
+            action => panic!("{:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut stub = stub();
+        // sp: empty stack, byte address RAM_BASE + 2 * 256
+        assert_eq!(reply(&mut stub, "g"), "0".repeat(40) + "0082");
+
+        let mut values = "0100".repeat(8);
+        values.push_str("0400"); // pc: byte address 4, i.e. word 2
+        values.push_str("0100");
+        values.push_str("fc81"); // sp: word 254
+        assert_eq!(reply(&mut stub, &format!("G{}", values)), "OK");
+        assert_eq!(stub.cpu().pc(), 2);
+        assert!(stub.cpu().flags().zero);
+        assert_eq!(stub.cpu().sp(), 254);
+
+        assert_eq!(reply(&mut stub, "P3=3412"), "OK");
+        assert_eq!(stub.cpu().register(Slot::Reg3), 0x1234);
+        assert_eq!(reply(&mut stub, "p3"), "3412");
+        assert_eq!(reply(&mut stub, "pb"), "E01");
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut stub = stub();
+        assert_eq!(reply(&mut stub, "m0,4"), "03410271");
+        assert_eq!(reply(&mut stub, "m6,1"), "E01");
+
+        assert_eq!(reply(&mut stub, "M8002,2:efbe"), "OK");
+        assert_eq!(stub.cpu().ram(1..2), Ok(&[0xbeef][..]));
+        assert_eq!(reply(&mut stub, "m8003,1"), "be");
+
+        assert_eq!(reply(&mut stub, "M1,1:42"), "OK");
+        assert_eq!(stub.cpu().rom().words()[0], 0b1000_010_00000011);
+        assert_eq!(reply(&mut stub, "M8000,2:00"), "E01");
+    }
+
+    #[test]
+    fn test_hostile_packets() {
+        let mut stub = stub();
+        assert_eq!(reply(&mut stub, "m1,ffffffffffffffff"), "E01");
+        assert_eq!(reply(&mut stub, "Mffffffffffffffff,2:0000"), "E01");
+        assert_eq!(
+            reply(
+                &mut stub,
+                "qXfer:features:read:target.xml:1,ffffffffffffffff"
+            ),
+            "E01"
+        );
+        assert_eq!(reply(&mut stub, "\u{fffd}m0,2"), "");
+    }
+
+    #[test]
+    fn test_read_packet_checksum() {
+        // The checksum covers the raw bytes, even when they are not UTF-8.
+        let mut raw = b"$\xffm#".to_vec();
+        raw.extend_from_slice(format!("{:02x}", 0xffu8.wrapping_add(b'm')).as_bytes());
+        let mut stream = io::Cursor::new(raw);
+        let packet = read_packet(&mut stream).unwrap().unwrap();
+        assert_eq!(packet, "\u{fffd}m");
+    }
+
+    #[test]
+    fn test_oversized_packet() {
+        let mut raw = vec![b'$'];
+        raw.resize(PACKET_SIZE + 2, b'a');
+        raw.extend_from_slice(b"#00$g#67");
+        let mut stream = io::Cursor::new(raw);
+        assert_eq!(read_packet(&mut stream).unwrap().unwrap(), "g");
+    }
+
+    #[test]
+    fn test_step_continue_and_breakpoints() {
+        let mut stub = stub();
+        assert_eq!(reply(&mut stub, "Z0,4,2"), "OK");
+        assert_eq!(reply(&mut stub, "s"), "S05");
+        assert_eq!(stub.cpu().pc(), 1);
+        assert_eq!(reply(&mut stub, "c"), "S05");
+        assert_eq!(stub.cpu().pc(), 2);
+        assert_eq!(reply(&mut stub, "z0,4,2"), "OK");
+        assert_eq!(reply(&mut stub, "c"), "W00");
+        assert_eq!(stub.handle("D", &mut || false), Action::Detach);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut stub = stub();
+        assert_eq!(reply(&mut stub, "Z2,8004,2"), "OK");
+        assert_eq!(reply(&mut stub, "c"), "T05watch:8004;");
+        assert_eq!(reply(&mut stub, "Z2,4,2"), "E01");
+        assert_eq!(reply(&mut stub, "Z4,8004,2"), "");
+    }
+
+    #[test]
+    fn test_target_xml() {
+        let mut stub = stub();
+        assert!(reply(&mut stub, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+
+        let first = reply(&mut stub, "qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, "m<?xml version=\"1");
+        let rest = reply(&mut stub, "qXfer:features:read:target.xml:10,1000");
+        assert!(rest.starts_with('l'));
+        assert!(rest.contains("<reg name=\"r7\" bitsize=\"16\" type=\"uint16\"/>"));
+        assert!(rest.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stub = stub();
+            stub.serve(stream).unwrap();
+            stub.cpu().is_halted()
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut exchange = |packet: &str, expected: &str| {
+            write_packet(&mut client, packet).unwrap();
+            let mut ack = [0];
+            client.read_exact(&mut ack).unwrap();
+            assert_eq!(ack, *b"+");
+            let mut response = vec![0; expected.len() + 4];
+            client.read_exact(&mut response).unwrap();
+            assert_eq!(
+                String::from_utf8(response).unwrap(),
+                format!("${}#{:02x}", expected, checksum(expected.as_bytes()))
+            );
+            client.write_all(b"+").unwrap();
+        };
+
+        exchange("?", "S05");
+        exchange("c", "W00");
+        exchange("m8004,2", "0300");
+        exchange("D", "OK");
+
+        assert!(server.join().unwrap());
+    }
+}
@@ -0,0 +1,505 @@
+use crate::asm::{self, Program};
+use crate::cpu_emu::Rom;
+use std::path::Path;
+use std::{fmt, fs};
+
+/// On-disk encodings of a ROM image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Raw 16-bit words, little-endian.
+    RawLe,
+    /// Raw 16-bit words, big-endian.
+    RawBe,
+    /// One hex word per line, optional `0x` prefix.
+    HexText,
+    /// One binary word per line, optional `0b` prefix and `_` separators.
+    BinText,
+    /// Verilog `$readmemh` input.
+    ReadMemH,
+    /// Verilog `$readmemb` input.
+    ReadMemB,
+    /// Intel HEX records; words are stored little-endian at byte address `2 * index`.
+    IntelHex,
+}
+
+impl ImageFormat {
+    pub const NAMES: &'static [(&'static str, ImageFormat)] = &[
+        ("raw-le", ImageFormat::RawLe),
+        ("raw-be", ImageFormat::RawBe),
+        ("hex", ImageFormat::HexText),
+        ("bin", ImageFormat::BinText),
+        ("memh", ImageFormat::ReadMemH),
+        ("memb", ImageFormat::ReadMemB),
+        ("ihex", ImageFormat::IntelHex),
+    ];
+
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        Self::NAMES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|&(_, format)| format)
+    }
+
+    /// Guesses the format from a file extension. `.hex` is Intel HEX, as it is
+    /// almost everywhere; one hex word per line is `.hexw`.
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        let format = match ext.as_str() {
+            "bin" | "rom" => ImageFormat::RawLe,
+            "hexw" => ImageFormat::HexText,
+            "txt" => ImageFormat::BinText,
+            "memh" | "mem" => ImageFormat::ReadMemH,
+            "memb" => ImageFormat::ReadMemB,
+            "hex" | "ihex" | "ihx" => ImageFormat::IntelHex,
+            _ => return None,
+        };
+        Some(format)
+    }
+}
+
+/// Words a ROM image may hold: the whole 16-bit word address space.
+const MAX_WORDS: usize = 1 << 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ImageError {
+    /// 1-based line of text formats; `None` for raw binary.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ImageError {
+    fn new(line: Option<usize>, message: String) -> Self {
+        Self { line, message }
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+pub fn load(bytes: &[u8], format: ImageFormat) -> Result<Rom, ImageError> {
+    let words = match format {
+        ImageFormat::RawLe => raw(bytes, u16::from_le_bytes)?,
+        ImageFormat::RawBe => raw(bytes, u16::from_be_bytes)?,
+        ImageFormat::HexText => word_per_line(text(bytes)?, 16)?,
+        ImageFormat::BinText => word_per_line(text(bytes)?, 2)?,
+        ImageFormat::ReadMemH => readmem(text(bytes)?, 16)?,
+        ImageFormat::ReadMemB => readmem(text(bytes)?, 2)?,
+        ImageFormat::IntelHex => intel_hex(text(bytes)?)?,
+    };
+    Ok(Rom::new(words))
+}
+
+/// Reads a program from disk. Files ending in `.s` or `.asm` are assembled
+/// unless `format` says otherwise; other images use `format`, the extension,
+/// or raw-le, and come without labels.
+///
+/// Errors are prefixed with the path, ready to print.
+pub fn open(path: &Path, format: Option<ImageFormat>) -> Result<Program, String> {
+    let read_error = |err| format!("{}: {}", path.display(), err);
+    let is_source = matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("s") | Some("asm")
+    );
+
+    if format.is_none() && is_source {
+        let source = fs::read_to_string(path).map_err(read_error)?;
+        return asm::assemble_program(&source).map_err(|err| format!("{}:{}", path.display(), err));
+    }
+
+    let format = format
+        .or_else(|| ImageFormat::from_path(path))
+        .unwrap_or(ImageFormat::RawLe);
+    let bytes = fs::read(path).map_err(read_error)?;
+    let rom = load(&bytes, format).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(Program {
+        words: rom.words().to_vec(),
+        labels: Default::default(),
+        lines: Vec::new(),
+    })
+}
+
+pub fn write(rom: &Rom, format: ImageFormat) -> Vec<u8> {
+    let words = rom.words();
+    match format {
+        ImageFormat::RawLe => words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+        ImageFormat::RawBe => words.iter().flat_map(|word| word.to_be_bytes()).collect(),
+        ImageFormat::HexText | ImageFormat::ReadMemH => {
+            lines(words, |word| format!("{:04x}", word))
+        }
+        ImageFormat::BinText | ImageFormat::ReadMemB => {
+            lines(words, |word| format!("{:016b}", word))
+        }
+        ImageFormat::IntelHex => write_intel_hex(words).into_bytes(),
+    }
+}
+
+fn lines<F: Fn(u16) -> String>(words: &[u16], render: F) -> Vec<u8> {
+    words
+        .iter()
+        .map(|&word| render(word) + "\n")
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn raw(bytes: &[u8], decode: fn([u8; 2]) -> u16) -> Result<Vec<u16>, ImageError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(ImageError::new(
+            None,
+            format!("odd number of bytes ({})", bytes.len()),
+        ));
+    }
+    if bytes.len() / 2 > MAX_WORDS {
+        return Err(ImageError::new(
+            None,
+            format!("{} words is more than the ROM holds", bytes.len() / 2),
+        ));
+    }
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| decode([pair[0], pair[1]]))
+        .collect())
+}
+
+fn text(bytes: &[u8]) -> Result<&str, ImageError> {
+    std::str::from_utf8(bytes).map_err(|err| {
+        let line = bytes[..err.valid_up_to()]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count();
+        ImageError::new(Some(line + 1), "invalid UTF-8".to_string())
+    })
+}
+
+fn strip_comment(line: &str) -> &str {
+    let line = line.split("//").next().unwrap_or("");
+    line.split('#').next().unwrap_or("").trim()
+}
+
+fn parse_word(text: &str, radix: u32, line: usize) -> Result<u16, ImageError> {
+    let digits = match radix {
+        16 => text.trim_start_matches("0x").trim_start_matches("0X"),
+        _ => text.trim_start_matches("0b").trim_start_matches("0B"),
+    };
+    let digits = digits.replace('_', "");
+    u16::from_str_radix(&digits, radix)
+        .map_err(|_| ImageError::new(Some(line), format!("invalid 16-bit word `{}`", text)))
+}
+
+fn word_per_line(text: &str, radix: u32) -> Result<Vec<u16>, ImageError> {
+    let mut words = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let body = strip_comment(line);
+        if body.is_empty() {
+            continue;
+        }
+        if words.len() == MAX_WORDS {
+            return Err(ImageError::new(
+                Some(index + 1),
+                "data is beyond the ROM".to_string(),
+            ));
+        }
+        words.push(parse_word(body, radix, index + 1)?);
+    }
+    Ok(words)
+}
+
+/// Parses `$readmemh`/`$readmemb` input: whitespace-separated words, `//` and
+/// `/* */` comments and `@addr` (hex word address) directives.
+fn readmem(text: &str, radix: u32) -> Result<Vec<u16>, ImageError> {
+    let mut words = Vec::new();
+    let mut addr = 0;
+    let mut in_block = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let mut rest = line;
+        let mut body = String::new();
+        loop {
+            if in_block {
+                match rest.find("*/") {
+                    Some(end) => {
+                        rest = &rest[end + 2..];
+                        in_block = false;
+                    }
+                    None => break,
+                }
+            } else {
+                let block = rest.find("/*");
+                let comment = rest.find("//");
+                match (block, comment) {
+                    (Some(start), Some(line_start)) if line_start < start => {
+                        body.push_str(&rest[..line_start]);
+                        break;
+                    }
+                    (Some(start), _) => {
+                        body.push_str(&rest[..start]);
+                        body.push(' ');
+                        rest = &rest[start + 2..];
+                        in_block = true;
+                    }
+                    (None, Some(line_start)) => {
+                        body.push_str(&rest[..line_start]);
+                        break;
+                    }
+                    (None, None) => {
+                        body.push_str(rest);
+                        break;
+                    }
+                }
+            }
+        }
+
+        for token in body.split_whitespace() {
+            if let Some(target) = token.strip_prefix('@') {
+                addr = usize::from_str_radix(target, 16).map_err(|_| {
+                    ImageError::new(Some(number), format!("invalid address `{}`", token))
+                })?;
+                continue;
+            }
+
+            let word = parse_word(token, radix, number)?;
+            if addr >= MAX_WORDS {
+                return Err(ImageError::new(
+                    Some(number),
+                    format!("address {:x} is beyond the ROM", addr),
+                ));
+            }
+            if words.len() <= addr {
+                words.resize(addr + 1, 0);
+            }
+            words[addr] = word;
+            addr += 1;
+        }
+    }
+
+    if in_block {
+        return Err(ImageError::new(
+            Some(text.lines().count()),
+            "unterminated `/*` comment".to_string(),
+        ));
+    }
+    Ok(words)
+}
+
+fn intel_hex(text: &str) -> Result<Vec<u16>, ImageError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut base = 0usize;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let error = |message: &str| ImageError::new(Some(number), message.to_string());
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| error("record does not start with `:`"))?;
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(error("invalid hex digit"));
+        }
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(error("truncated record"));
+        }
+        let record = (0..hex.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&hex[at..at + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| error("invalid hex digit"))?;
+
+        let length = record[0] as usize;
+        if record.len() != length + 5 {
+            return Err(error("record length does not match byte count"));
+        }
+        let checksum = record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        if checksum != 0 {
+            return Err(error("checksum mismatch"));
+        }
+
+        let offset = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..4 + length];
+        match record[3] {
+            0x00 => {
+                let start = base + offset;
+                if start + length > 2 * MAX_WORDS {
+                    return Err(error("data is beyond the ROM"));
+                }
+                if bytes.len() < start + length {
+                    bytes.resize(start + length, 0);
+                }
+                bytes[start..start + length].copy_from_slice(data);
+            }
+            0x01 => break,
+            0x02 if length == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4,
+            0x04 if length == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16,
+            0x02 | 0x04 => return Err(error("address record must hold 2 bytes")),
+            0x03 | 0x05 => {}
+            kind => return Err(error(&format!("unsupported record type {:02x}", kind))),
+        }
+    }
+
+    if !bytes.len().is_multiple_of(2) {
+        bytes.push(0);
+    }
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+fn write_intel_hex(words: &[u16]) -> String {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let mut out = String::new();
+    let mut upper = 0;
+
+    for (index, chunk) in bytes.chunks(16).enumerate() {
+        let addr = index * 16;
+        if addr >> 16 != upper {
+            upper = addr >> 16;
+            out.push_str(&record(0, 0x04, &(upper as u16).to_be_bytes()));
+        }
+        out.push_str(&record(addr as u16, 0x00, chunk));
+    }
+    out.push_str(&record(0, 0x01, &[]));
+    out
+}
+
+fn record(addr: u16, kind: u8, data: &[u8]) -> String {
+    let mut fields = vec![data.len() as u8];
+    fields.extend_from_slice(&addr.to_be_bytes());
+    fields.push(kind);
+    fields.extend_from_slice(data);
+    let checksum = fields
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg();
+    fields.push(checksum);
+
+    let hex: String = fields.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}\n", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8], format: ImageFormat) -> Vec<u16> {
+        load(bytes, format).unwrap().words().to_vec()
+    }
+
+    fn sample() -> Rom {
+        Rom::new(vec![
+            0b1000_000_00000001,
+            0b0001_010_000_00000,
+            0xffff,
+            0x7800,
+        ])
+    }
+
+    #[test]
+    fn test_round_trip_all_formats() {
+        for &(name, format) in ImageFormat::NAMES {
+            let bytes = write(&sample(), format);
+            assert_eq!(words(&bytes, format), sample().words(), "format {}", name);
+        }
+    }
+
+    #[test]
+    fn test_raw() {
+        assert_eq!(words(&[0x01, 0x80], ImageFormat::RawLe), vec![0x8001]);
+        assert_eq!(words(&[0x01, 0x80], ImageFormat::RawBe), vec![0x0180]);
+
+        let err = load(&[0x01], ImageFormat::RawLe).unwrap_err();
+        assert_eq!(err.line, None);
+
+        let err = load(&vec![0; 2 * MAX_WORDS + 2], ImageFormat::RawBe).unwrap_err();
+        assert_eq!(err.to_string(), "65537 words is more than the ROM holds");
+    }
+
+    #[test]
+    fn test_text() {
+        let text = b"0x4001 // ldl r0, 1\n\n1400\n";
+        assert_eq!(words(text, ImageFormat::HexText), vec![0x4001, 0x1400]);
+
+        let text = b"1000_000_00000001\n0b1\n";
+        assert_eq!(words(text, ImageFormat::BinText), vec![0x4001, 1]);
+
+        let err = load(b"0001\n10000\n", ImageFormat::HexText).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid 16-bit word `10000`");
+
+        let text = "0\n".repeat(MAX_WORDS + 1);
+        let err = load(text.as_bytes(), ImageFormat::HexText).unwrap_err();
+        assert_eq!(err.to_string(), "line 65537: data is beyond the ROM");
+    }
+
+    #[test]
+    fn test_readmem() {
+        let text = b"/* header\n comment */ 4001 1400\n@8 // jump ahead\n7800\n";
+        assert_eq!(
+            words(text, ImageFormat::ReadMemH),
+            vec![0x4001, 0x1400, 0, 0, 0, 0, 0, 0, 0x7800]
+        );
+
+        let text = b"0111_1000_0000_0000\n";
+        assert_eq!(words(text, ImageFormat::ReadMemB), vec![0x7800]);
+
+        let err = load(b"4001\nxxxx\n", ImageFormat::ReadMemH).unwrap_err();
+        assert_eq!(err.line, Some(2));
+
+        let err = load(b"@ffffffffffff 0000\n", ImageFormat::ReadMemH).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: address ffffffffffff is beyond the ROM"
+        );
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let text = b":0400000001400014A7\n:00000001FF\n";
+        assert_eq!(words(text, ImageFormat::IntelHex), vec![0x4001, 0x1400]);
+
+        let err = load(
+            b":0400000001400014A7\n:0400000001400014A8\n",
+            ImageFormat::IntelHex,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "line 2: checksum mismatch");
+
+        let err = load(":0\u{e9}00000001FF\n".as_bytes(), ImageFormat::IntelHex).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid hex digit");
+
+        // An extended linear address of 0xffff puts the data near 4 GiB.
+        let text = b":02000004FFFFFC\n:0100000000FF\n";
+        let err = load(text, ImageFormat::IntelHex).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: data is beyond the ROM");
+
+        let err = load(b":0100000400FB\n", ImageFormat::IntelHex).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: address record must hold 2 bytes");
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.memh")),
+            Some(ImageFormat::ReadMemH)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.HEX")),
+            Some(ImageFormat::IntelHex)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.hexw")),
+            Some(ImageFormat::HexText)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("a.s")), None);
+    }
+}
@@ -0,0 +1,13 @@
+// Instruction literals are grouped by field (code_reg-a_reg-b_rest), not by nibble.
+#![allow(clippy::unusual_byte_groupings)]
+
+pub mod asm;
+pub mod clike;
+pub mod cpu_emu;
+pub mod dap;
+pub mod debugger;
+pub mod devices;
+pub mod disasm;
+pub mod gdb;
+pub mod image;
+pub mod pipeline;
@@ -1,11 +1,420 @@
-mod clike;
-mod cpu_emu;
+use rust_risc_emu::asm::Program;
+use rust_risc_emu::cpu_emu::{
+    Arithmetic, CpuEmu, CsvTracer, JsonTracer, Rom, StopReason, TextTracer,
+};
+use rust_risc_emu::devices::{
+    framebuffer, timer, uart, Background, Frame, Framebuffer, Timer, Uart, FRAMEBUFFER_BASE,
+    TIMER_BASE, UART_BASE,
+};
+use rust_risc_emu::gdb::GdbStub;
+use rust_risc_emu::image::{self, ImageFormat};
+use rust_risc_emu::pipeline::{CycleRecord, Hazards, Pipeline};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::{env, fs, io};
+
+const USAGE: &str = "\
+Usage: rust_risc_emu [OPTIONS] <PROGRAM>
+
+Runs PROGRAM on the emulator. Files ending in `.s` or `.asm` are assembled
+first; other images are read in the format given by --format, guessed from
+the extension (.bin .hexw .txt .memh .memb, .hex or .ihex for Intel HEX),
+or as raw-le.
+
+A UART at ram[0xff00] (data) and ram[0xff01] (status: 1 = byte waiting,
+2 = ready to send, 4 = end of input) is connected to stdin and stdout.
+A timer at ram[0xff10..0xff14] (load, count, control, status) counts
+cycles and raises interrupt line 0; see --vectors.
+A 32x32 framebuffer at ram[0xfe00..0xfe40] (two words per row, leftmost
+pixel in bit 15) presents a frame when the program writes ram[0xfe40].
+
+Options:
+  --format <FMT>   image format: raw-le, raw-be, hex, bin, memh, memb, ihex
+  --emit <PATH>    write the loaded ROM to PATH (format from its extension)
+                   instead of running it
+  --max-steps <N>  stop after executing N instructions
+  --trace          print every executed instruction and its effects
+  --trace-format <FMT>
+                   trace as text, csv or json (one object per line);
+                   implies --trace
+  --dump           print registers and RAM when the program stops
+  --display        draw every presented frame on the terminal
+  --frames <DIR>   save every presented frame as DIR/frame-NNNN.ppm
+  --trap-overflow  stop with an error when add, sub or sl carries out of
+                   16 bits instead of wrapping around
+  --vectors <ADDR> put the interrupt and trap vector table at ram[ADDR]
+                   (decimal, or hex with 0x),
+                   so faults jump to the handler stored there
+  --pipeline <MODE>
+                   run on the five-stage pipeline model and print what each
+                   stage holds every cycle; MODE is forward (bypass results
+                   to EX) or stall (wait for write-back). Devices and the
+                   cycle count follow pipeline cycles, and --max-steps
+                   limits them; cannot be combined with --trace
+  --gdb <PORT>     wait for a GDB connection on 127.0.0.1:PORT and let it
+                   drive the program instead of running it
+  -h, --help       show this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TraceFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl TraceFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "csv" => Some(TraceFormat::Csv),
+            "json" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Options {
+    program: PathBuf,
+    format: Option<ImageFormat>,
+    emit: Option<PathBuf>,
+    max_steps: Option<usize>,
+    gdb: Option<u16>,
+    trace: Option<TraceFormat>,
+    dump: bool,
+    display: bool,
+    frames: Option<PathBuf>,
+    trap_overflow: bool,
+    vectors: Option<usize>,
+    pipeline: Option<Hazards>,
+}
+
+impl Options {
+    /// Parses the arguments after the program name; `Ok(None)` means help was requested.
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>, String> {
+        let mut options = Options::default();
+        let mut program = None;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--trace" => {
+                    options.trace = options.trace.or(Some(TraceFormat::Text));
+                }
+                "--dump" => options.dump = true,
+                "--display" => options.display = true,
+                "--frames" => {
+                    let value = args.next().ok_or("--frames needs a directory")?;
+                    options.frames = Some(PathBuf::from(value));
+                }
+                "--trap-overflow" => options.trap_overflow = true,
+                "--format" => {
+                    let value = args.next().ok_or("--format needs a value")?;
+                    let format = ImageFormat::from_name(&value)
+                        .ok_or_else(|| format!("unknown format `{}`", value))?;
+                    options.format = Some(format);
+                }
+                "--trace-format" => {
+                    let value = args.next().ok_or("--trace-format needs a value")?;
+                    let format = TraceFormat::from_name(&value)
+                        .ok_or_else(|| format!("unknown trace format `{}`", value))?;
+                    options.trace = Some(format);
+                }
+                "--emit" => {
+                    let value = args.next().ok_or("--emit needs a path")?;
+                    options.emit = Some(PathBuf::from(value));
+                }
+                "--max-steps" => {
+                    let value = args.next().ok_or("--max-steps needs a value")?;
+                    let steps = value
+                        .parse()
+                        .map_err(|_| format!("invalid step count `{}`", value))?;
+                    options.max_steps = Some(steps);
+                }
+                "--vectors" => {
+                    let value = args.next().ok_or("--vectors needs an address")?;
+                    let addr = match value.strip_prefix("0x") {
+                        Some(hex) => usize::from_str_radix(hex, 16),
+                        None => value.parse(),
+                    };
+                    let addr = addr.map_err(|_| format!("invalid address `{}`", value))?;
+                    options.vectors = Some(addr);
+                }
+                "--pipeline" => {
+                    let value = args.next().ok_or("--pipeline needs a mode")?;
+                    let hazards = Hazards::from_name(&value)
+                        .ok_or_else(|| format!("unknown pipeline mode `{}`", value))?;
+                    options.pipeline = Some(hazards);
+                }
+                "--gdb" => {
+                    let value = args.next().ok_or("--gdb needs a port")?;
+                    let port = value
+                        .parse()
+                        .map_err(|_| format!("invalid port `{}`", value))?;
+                    options.gdb = Some(port);
+                }
+                flag if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+                path if program.is_none() => program = Some(PathBuf::from(path)),
+                extra => return Err(format!("unexpected argument `{}`", extra)),
+            }
+        }
+
+        options.program = program.ok_or("missing PROGRAM")?;
+        if options.pipeline.is_some() && options.trace.is_some() {
+            return Err("--trace cannot be combined with --pipeline".to_string());
+        }
+        Ok(Some(options))
+    }
+}
+
+fn emit(rom: &Rom, path: &Path) -> Result<(), String> {
+    let format = ImageFormat::from_path(path)
+        .ok_or_else(|| format!("{}: cannot tell the image format", path.display()))?;
+    fs::write(path, image::write(rom, format)).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Maps the console, the timer and the framebuffer, as described in `USAGE`.
+fn attach_devices(cpu: &mut CpuEmu, options: &Options) {
+    let console = Uart::new(Background::spawn(io::stdin()), io::stdout());
+    cpu.bus_mut()
+        .attach(UART_BASE..UART_BASE + uart::LEN, console);
+    cpu.bus_mut()
+        .attach(TIMER_BASE..TIMER_BASE + timer::LEN, Timer::new(0));
+
+    let mut framebuffer = Framebuffer::new();
+    let (display, frames) = (options.display, options.frames.clone());
+    framebuffer.on_frame(move |frame| {
+        if display {
+            println!("frame {}:\n{}", frame.number, frame);
+        }
+        if let Some(dir) = &frames {
+            if let Err(msg) = save_frame(dir, frame) {
+                eprintln!("error: {}", msg);
+            }
+        }
+    });
+    cpu.bus_mut().attach(
+        FRAMEBUFFER_BASE..FRAMEBUFFER_BASE + framebuffer::LEN,
+        framebuffer,
+    );
+}
+
+fn save_frame(dir: &Path, frame: &Frame) -> Result<(), String> {
+    let path = dir.join(format!("frame-{:04}.ppm", frame.number));
+    fs::write(&path, frame.to_ppm()).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn execute(cpu: &mut CpuEmu, options: &Options) -> StopReason {
+    match options.trace {
+        Some(TraceFormat::Text) => cpu.set_tracer(TextTracer::new(io::stdout())),
+        Some(TraceFormat::Csv) => cpu.set_tracer(CsvTracer::new(io::stdout())),
+        Some(TraceFormat::Json) => cpu.set_tracer(JsonTracer::new(io::stdout())),
+        None => {}
+    }
+
+    match options.max_steps {
+        Some(max) => cpu.run_for(max),
+        None => cpu.run_until(|_| false),
+    }
+}
+
+/// Runs the pipeline model, printing a diagram row per cycle and a summary.
+/// `BudgetExhausted` means the cycle limit was reached before `hlt` drained.
+///
+/// The pipeline clocks the devices and the CPU's cycle count, so `--dump`
+/// shows the same cycles as the summary.
+fn simulate(cpu: CpuEmu, options: &Options, hazards: Hazards) -> (CpuEmu, StopReason) {
+    let mut pipeline = Pipeline::new(cpu, hazards);
+    let limit = options.max_steps.map_or(u64::MAX, |max| max as u64);
+
+    println!("{}", CycleRecord::header());
+    while !pipeline.is_finished() {
+        if pipeline.stats().cycles >= limit {
+            return (pipeline.into_cpu(), StopReason::BudgetExhausted);
+        }
+        match pipeline.cycle() {
+            Ok(record) => println!("{}", record),
+            Err(err) => return (pipeline.into_cpu(), StopReason::Fault(err)),
+        }
+    }
+
+    let stats = pipeline.stats();
+    println!(
+        "{} cycles, {} instructions, CPI {:.2}, {} stalls, {} flushed",
+        stats.cycles,
+        stats.retired,
+        stats.cycles as f64 / stats.retired.max(1) as f64,
+        stats.stalls,
+        stats.flushed
+    );
+    let reason = pipeline.stopped().cloned().unwrap_or(StopReason::Halted);
+    (pipeline.into_cpu(), reason)
+}
+
+/// Serves one GDB session and hands the CPU back once it ends.
+fn debug(cpu: CpuEmu, port: u16) -> io::Result<CpuEmu> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for gdb on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    eprintln!("gdb connected from {}", peer);
+
+    let mut stub = GdbStub::new(cpu);
+    stub.serve(stream)?;
+    Ok(stub.into_cpu())
+}
 
 fn main() {
-    clike::emulate();
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let rom = image::open(&options.program, options.format)
+        .map(Program::into_rom)
+        .unwrap_or_else(|msg| {
+            eprintln!("error: {}", msg);
+            process::exit(1);
+        });
+
+    if let Some(path) = &options.emit {
+        if let Err(msg) = emit(&rom, path) {
+            eprintln!("error: {}", msg);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut cpu = CpuEmu::new(rom);
+    attach_devices(&mut cpu, &options);
+    if options.trap_overflow {
+        cpu.set_arithmetic(Arithmetic::Trapping);
+    }
+    if let Err(err) = cpu.set_vectors(options.vectors) {
+        eprintln!("error: --vectors: {}", err);
+        process::exit(2);
+    }
+    if let Some(port) = options.gdb {
+        cpu = debug(cpu, port).unwrap_or_else(|err| {
+            eprintln!("error: gdb: {}", err);
+            process::exit(1);
+        });
+        if options.dump {
+            print!("{}", cpu.snapshot());
+        }
+        return;
+    }
+    let reason = match options.pipeline {
+        Some(hazards) => {
+            let (stopped, reason) = simulate(cpu, &options, hazards);
+            cpu = stopped;
+            reason
+        }
+        None => execute(&mut cpu, &options),
+    };
+
+    if options.dump {
+        print!("{}", cpu.snapshot());
+    }
+
+    match reason {
+        StopReason::Halted => {}
+        StopReason::Fault(err) => {
+            eprintln!("error: {} (pc {})", err, cpu.pc());
+            process::exit(1);
+        }
+        _ => {
+            eprintln!("stopped: step limit reached before `hlt`");
+            process::exit(3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse(&["--trace", "prog.s", "--max-steps", "100", "--dump"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                program: PathBuf::from("prog.s"),
+                max_steps: Some(100),
+                trace: Some(TraceFormat::Text),
+                dump: true,
+                ..Options::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_trace_format() {
+        let options = parse(&["--trace-format", "csv", "--trace", "prog.s"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.trace, Some(TraceFormat::Csv));
+    }
+
+    #[test]
+    fn test_parse_trap_overflow() {
+        let options = parse(&["--trap-overflow", "prog.s"]).unwrap().unwrap();
+        assert!(options.trap_overflow);
+        assert!(!parse(&["prog.s"]).unwrap().unwrap().trap_overflow);
+    }
+
+    #[test]
+    fn test_parse_display() {
+        let options = parse(&["--display", "--frames", "out", "demo.s"])
+            .unwrap()
+            .unwrap();
+        assert!(options.display);
+        assert_eq!(options.frames, Some(PathBuf::from("out")));
+        assert!(parse(&["demo.s", "--frames"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_vectors() {
+        let options = parse(&["--vectors", "224", "prog.s"]).unwrap().unwrap();
+        assert_eq!(options.vectors, Some(224));
+        let options = parse(&["--vectors", "0xe0", "prog.s"]).unwrap().unwrap();
+        assert_eq!(options.vectors, Some(0xe0));
+        assert!(parse(&["--vectors", "x", "prog.s"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let options = parse(&["--pipeline", "stall", "prog.s"]).unwrap().unwrap();
+        assert_eq!(options.pipeline, Some(Hazards::Stalling));
+        assert!(parse(&["--pipeline", "guess", "prog.s"]).is_err());
+        assert!(parse(&["--pipeline", "forward", "--trace", "prog.s"]).is_err());
+        assert!(parse(&["--trace-format", "json", "--pipeline", "stall", "prog.s"]).is_err());
+    }
 
-    let rom = cpu_emu::Rom::new(vec![0b1111_000_000_00000]);
-    if let Err(msg) = cpu_emu::CpuEmu::new(rom).run() {
-        panic!(msg);
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse(&["--help"]), Ok(None));
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["a.s", "b.s"]).is_err());
+        assert!(parse(&["--max-steps", "x", "a.s"]).is_err());
+        assert!(parse(&["--verbose", "a.s"]).is_err());
+        assert!(parse(&["--format", "elf", "a.bin"]).is_err());
+        assert!(parse(&["--trace-format", "xml", "a.s"]).is_err());
+        assert!(parse(&["--gdb", "70000", "a.s"]).is_err());
     }
 }
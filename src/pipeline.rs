@@ -0,0 +1,802 @@
+//! A five-stage (IF, ID, EX, MEM, WB) pipeline model of the ISA.
+//!
+//! The pipeline is a timing model layered over `CpuEmu`: each instruction's
+//! architectural effects are applied by `CpuEmu::step` as it enters EX, in
+//! program order, so the final state always matches `CpuEmu::run`. What the
+//! model adds is where every instruction is on each cycle:
+//!
+//! - fetch predicts fall-through; jumps resolve in EX, and a redirect squashes
+//!   the two younger instructions in IF and ID;
+//! - an instruction waits in ID while it reads a register, the flags or `sp`
+//!   that an older one has not produced yet. With `Hazards::Forwarding` only a
+//!   value loaded in MEM (`ld`, `pop`) costs a bubble; with `Hazards::Stalling`
+//!   results are only visible once the producer reaches WB.
+//!
+//! Interrupts and traps are redirects too. An interrupt is taken as an
+//! instruction enters EX, in its place: it leaves EX empty, squashes IF and ID
+//! and fetches the handler, and `reti` later refetches the interrupted
+//! instruction. A trap squashes the faulting instruction in EX the same way.
+//!
+//! The pipeline keeps the clock: devices tick once per pipeline cycle and
+//! `CpuEmu::cycles` counts pipeline cycles, not the CPU's `Timing` table,
+//! which is set aside until `into_cpu`. A timer's period is therefore in
+//! pipeline cycles, and its interrupts may land on other instructions than
+//! under `CpuEmu::run`.
+//!
+//! A breakpoint stops fetch once the CPU reaches it, so the instruction at it
+//! never runs, and a watchpoint stops it after the instruction that hit it;
+//! what is older drains, and `stopped` says why the run ended.
+
+use crate::cpu_emu::{CpuEmu, EmuError, Opcode, Slot, StopReason, Timing};
+use std::fmt;
+
+/// How data hazards are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hazards {
+    /// Results are bypassed from EX and MEM to the next EX.
+    Forwarding,
+    /// No bypass: consumers wait until the producer writes back.
+    Stalling,
+}
+
+impl Hazards {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "forward" => Some(Hazards::Forwarding),
+            "stall" => Some(Hazards::Stalling),
+            _ => None,
+        }
+    }
+}
+
+pub const STAGES: [&str; 5] = ["IF", "ID", "EX", "MEM", "WB"];
+
+const IF: usize = 0;
+const ID: usize = 1;
+const EX: usize = 2;
+const MEM: usize = 3;
+const WB: usize = 4;
+
+/// Registers, flags and stack pointer as a bit set: bit `n` is `rn`.
+type Resources = u16;
+
+const FLAGS: Resources = 1 << 8;
+const SP: Resources = 1 << 9;
+
+fn reg(slot: Slot) -> Resources {
+    1 << slot as u16
+}
+
+fn reads(code: &Opcode) -> Resources {
+    use Opcode::*;
+    match *code {
+        Mov(_, b) => reg(b),
+        Add(a, b) | Sub(a, b) | And(a, b) | Or(a, b) | Cmp(a, b) | StInd(a, b) => reg(a) | reg(b),
+        Sl(a) | Sr(a) | Sra(a) | Ldl(a, _) | Ldh(a, _) | St(a, _) => reg(a),
+        LdInd(_, b) | JmpInd(b) => reg(b),
+        Je(_) | Jne(_) | Jlt(_) | Jge(_) | Jc(_) | Jnc(_) => FLAGS,
+        Push(a) => reg(a) | SP,
+        Pop(_) | Call(_) | Ret => SP,
+        Jmp(_) | Ld(..) | Hlt | Ei | Di | Reti => 0,
+    }
+}
+
+fn writes(code: &Opcode) -> Resources {
+    use Opcode::*;
+    match *code {
+        Mov(a, _) | Ldl(a, _) | Ldh(a, _) | Ld(a, _) | LdInd(a, _) => reg(a),
+        Add(a, _) | Sub(a, _) | And(a, _) | Or(a, _) | Sl(a) | Sr(a) | Sra(a) => reg(a) | FLAGS,
+        Cmp(..) | Reti => FLAGS,
+        Pop(a) => reg(a) | SP,
+        Push(_) | Call(_) | Ret => SP,
+        _ => 0,
+    }
+}
+
+/// Results that only exist after MEM, so forwarding cannot hide them.
+fn loads(code: &Opcode) -> Resources {
+    match *code {
+        Opcode::Ld(a, _) | Opcode::LdInd(a, _) | Opcode::Pop(a) => reg(a),
+        _ => 0,
+    }
+}
+
+fn names(resources: Resources) -> String {
+    let mut names: Vec<String> = Slot::ALL
+        .iter()
+        .filter(|&&slot| resources & reg(slot) != 0)
+        .map(|slot| slot.to_string())
+        .collect();
+    if resources & FLAGS != 0 {
+        names.push("flags".to_string());
+    }
+    if resources & SP != 0 {
+        names.push("sp".to_string());
+    }
+    names.join(", ")
+}
+
+/// An instruction in a pipeline stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InFlight {
+    pub pc: usize,
+    /// `None` if the word does not decode, or the PC is past the ROM.
+    pub code: Option<Opcode>,
+}
+
+impl InFlight {
+    fn reads(&self) -> Resources {
+        self.code.as_ref().map_or(0, reads)
+    }
+
+    fn writes(&self) -> Resources {
+        self.code.as_ref().map_or(0, writes)
+    }
+
+    fn loads(&self) -> Resources {
+        self.code.as_ref().map_or(0, loads)
+    }
+}
+
+impl fmt::Display for InFlight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{:>3} {}", self.pc, code),
+            None => write!(f, "{:>3} ?", self.pc),
+        }
+    }
+}
+
+/// One line of the pipeline diagram: what each stage held during a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleRecord {
+    pub cycle: u64,
+    pub stages: [Option<InFlight>; 5],
+    /// Stalls, forwarding, squashes and interrupts that happened this cycle.
+    pub notes: Vec<String>,
+}
+
+const CELL: usize = 16;
+
+impl CycleRecord {
+    /// Column titles matching `Display`.
+    pub fn header() -> String {
+        let stages: String = STAGES
+            .iter()
+            .map(|stage| format!("{:<width$}", stage, width = CELL))
+            .collect();
+        format!("cycle  {}", stages).trim_end().to_string()
+    }
+}
+
+impl fmt::Display for CycleRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut line = format!("{:>5}  ", self.cycle);
+        for stage in &self.stages {
+            let mut cell = stage.as_ref().map_or("-".to_string(), |i| i.to_string());
+            cell.truncate(CELL - 1);
+            line.push_str(&format!("{:<width$}", cell, width = CELL));
+        }
+        line.push_str(&self.notes.join("; "));
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+/// Totals over a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub cycles: u64,
+    /// Instructions that reached WB.
+    pub retired: u64,
+    /// Bubbles inserted by data hazards.
+    pub stalls: u64,
+    /// Instructions squashed by redirects.
+    pub flushed: u64,
+}
+
+pub struct Pipeline {
+    cpu: CpuEmu,
+    hazards: Hazards,
+    stages: [Option<InFlight>; 5],
+    /// Next address to fetch; `None` once `hlt` has executed.
+    fetch_pc: Option<usize>,
+    /// Squash IF and ID at the start of the next cycle.
+    squash: bool,
+    /// The breakpoint or watchpoint that stopped fetch.
+    stopped: Option<StopReason>,
+    /// The CPU's own timing, restored by `into_cpu`.
+    timing: Timing,
+    stats: PipelineStats,
+}
+
+impl Pipeline {
+    pub fn new(mut cpu: CpuEmu, hazards: Hazards) -> Self {
+        let fetch_pc = (!cpu.is_halted()).then(|| cpu.pc());
+        let timing = cpu.timing().clone();
+        cpu.set_timing(Timing::zero());
+        Self {
+            cpu,
+            hazards,
+            stages: Default::default(),
+            fetch_pc,
+            squash: false,
+            stopped: None,
+            timing,
+            stats: PipelineStats::default(),
+        }
+    }
+
+    pub fn cpu(&self) -> &CpuEmu {
+        &self.cpu
+    }
+
+    pub fn into_cpu(mut self) -> CpuEmu {
+        self.cpu.set_timing(self.timing);
+        self.cpu
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        self.stats
+    }
+
+    /// The breakpoint or watchpoint that ended the run, if any.
+    pub fn stopped(&self) -> Option<&StopReason> {
+        self.stopped.as_ref()
+    }
+
+    /// Whether `hlt`, a breakpoint or a watchpoint has stopped fetch and
+    /// everything older has reached WB.
+    pub fn is_finished(&self) -> bool {
+        self.fetch_pc.is_none() && self.stages[..WB].iter().all(Option::is_none)
+    }
+
+    /// Runs until finished, reporting each cycle to `on_cycle`.
+    pub fn run<F>(&mut self, mut on_cycle: F) -> Result<PipelineStats, EmuError>
+    where
+        F: FnMut(&CycleRecord),
+    {
+        while !self.is_finished() {
+            let record = self.cycle()?;
+            on_cycle(&record);
+        }
+        Ok(self.stats)
+    }
+
+    /// Advances every stage by one cycle.
+    ///
+    /// A fault is returned as soon as the faulting instruction enters EX,
+    /// leaving the CPU exactly where `CpuEmu::run` would.
+    pub fn cycle(&mut self) -> Result<CycleRecord, EmuError> {
+        self.stats.cycles += 1;
+        self.cpu.elapse(1);
+        let mut notes = Vec::new();
+        let [fetched, decoded, executing, memory, _] = std::mem::take(&mut self.stages);
+        let stall = match &decoded {
+            Some(consumer) if !self.squash => {
+                self.hazard(consumer, executing.as_ref(), memory.as_ref())
+            }
+            _ => 0,
+        };
+        let produced = executing.as_ref().map_or(0, InFlight::writes)
+            | memory.as_ref().map_or(0, InFlight::writes);
+        let bypassed = decoded.as_ref().map_or(0, InFlight::reads) & produced;
+
+        if memory.is_some() {
+            self.stats.retired += 1;
+        }
+        self.stages[WB] = memory;
+        self.stages[MEM] = executing;
+
+        let mut entered = false;
+        if self.squash {
+            self.squash = false;
+            let squashed = [&fetched, &decoded].iter().filter(|s| s.is_some()).count();
+            if squashed > 0 {
+                self.stats.flushed += squashed as u64;
+                notes.push(format!("flush {}", squashed));
+            }
+            self.stages[IF] = self.fetch();
+        } else if stall != 0 {
+            self.stats.stalls += 1;
+            notes.push(format!("stall: {}", names(stall)));
+            self.stages[ID] = decoded;
+            self.stages[IF] = fetched;
+        } else {
+            if self.hazards == Hazards::Forwarding && bypassed != 0 {
+                notes.push(format!("forward: {}", names(bypassed)));
+            }
+            entered = decoded.is_some();
+            self.stages[EX] = decoded;
+            self.stages[ID] = fetched;
+            self.stages[IF] = self.fetch();
+        }
+
+        if entered {
+            self.execute(&mut notes)?;
+        }
+
+        Ok(CycleRecord {
+            cycle: self.stats.cycles,
+            stages: self.stages.clone(),
+            notes,
+        })
+    }
+
+    /// Resources `consumer` in ID must wait for before it may enter EX.
+    fn hazard(
+        &self,
+        consumer: &InFlight,
+        executing: Option<&InFlight>,
+        writing_back: Option<&InFlight>,
+    ) -> Resources {
+        match self.hazards {
+            Hazards::Forwarding => consumer.reads() & executing.map_or(0, InFlight::loads),
+            Hazards::Stalling => {
+                consumer.reads()
+                    & (executing.map_or(0, InFlight::writes)
+                        | writing_back.map_or(0, InFlight::writes))
+            }
+        }
+    }
+
+    fn fetch(&mut self) -> Option<InFlight> {
+        let pc = self.fetch_pc?;
+        self.fetch_pc = Some(pc + 1);
+        let code = self
+            .cpu
+            .rom()
+            .read(pc)
+            .ok()
+            .and_then(|word| Opcode::decode(word).ok());
+        Some(InFlight { pc, code })
+    }
+
+    /// Applies the instruction that just entered EX and redirects fetch if it
+    /// did not fall through, or takes a pending interrupt instead.
+    fn execute(&mut self, notes: &mut Vec<String>) -> Result<(), EmuError> {
+        let pc = self.stages[EX].as_ref().map_or(0, |i| i.pc);
+        debug_assert_eq!(pc, self.cpu.pc());
+
+        if self.cpu.interrupt()? {
+            self.squash_ex("interrupt", notes);
+            return Ok(());
+        }
+
+        let in_handler = self.cpu.interrupts().saved().is_some();
+        let executed = self.cpu.executed();
+        let reason = self.cpu.step();
+        if let StopReason::Fault(err) = reason {
+            return Err(err);
+        }
+        if reason == StopReason::Breakpoint(pc)
+            && self.cpu.executed() == executed
+            && self.cpu.pc() == pc
+        {
+            // Stopped before it ran: leave it to whoever resumes.
+            notes.push(format!("breakpoint at {}", pc));
+            self.stages[EX] = None;
+            self.stop(Some(reason));
+            return Ok(());
+        }
+        let trapped = !in_handler && self.cpu.interrupts().saved().is_some();
+
+        if trapped {
+            self.squash_ex("trap", notes);
+        } else if self.cpu.is_halted() {
+            self.stop(None);
+        } else if self.cpu.pc() != pc + 1 {
+            self.fetch_pc = Some(self.cpu.pc());
+            self.squash = true;
+        }
+
+        // It ran, but the next instruction is at a breakpoint or it hit a
+        // watchpoint.
+        match reason {
+            StopReason::Breakpoint(next) => notes.push(format!("breakpoint at {}", next)),
+            StopReason::Watchpoint(hit) => notes.push(format!("watchpoint: {}", hit.watchpoint)),
+            _ => return Ok(()),
+        }
+        self.stop(Some(reason));
+        Ok(())
+    }
+
+    /// Stops fetching and drops IF and ID. Nothing younger than the
+    /// instruction in EX runs, so they do not count as flushed.
+    fn stop(&mut self, reason: Option<StopReason>) {
+        self.stopped = reason;
+        self.fetch_pc = None;
+        self.squash = false;
+        self.stages[IF] = None;
+        self.stages[ID] = None;
+    }
+
+    /// Drops the instruction in EX, which did not complete, and redirects
+    /// fetch to the handler the CPU entered.
+    fn squash_ex(&mut self, cause: &str, notes: &mut Vec<String>) {
+        notes.push(cause.to_string());
+        self.stages[EX] = None;
+        self.stats.flushed += 1;
+        self.fetch_pc = Some(self.cpu.pc());
+        self.squash = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::cpu_emu::{Arithmetic, MachineState, Watchpoint};
+    use crate::devices::{timer, Timer, TIMER_BASE};
+
+    fn pipeline(source: &str, hazards: Hazards) -> Pipeline {
+        Pipeline::new(CpuEmu::new(assemble(source).unwrap()), hazards)
+    }
+
+    fn run(source: &str, hazards: Hazards) -> (Pipeline, Vec<CycleRecord>) {
+        let mut pipeline = pipeline(source, hazards);
+        let mut records = Vec::new();
+        pipeline.run(|record| records.push(record.clone())).unwrap();
+        (pipeline, records)
+    }
+
+    const PROGRAMS: [&str; 4] = [
+        // sum 1..10 into ram[64]
+        "
+            ldl r1, 10
+            ldl r3, 1
+        loop:
+            add r2, r1
+            sub r1, r3
+            cmp r1, r0
+            jne loop
+            st r2, 64
+            ld r4, 64
+            add r4, r4
+            hlt
+        ",
+        // copy through pointers, then jump through a register
+        "
+            ldl r1, 1
+            ldl r2, 9
+            ld r0, 0
+            st r0, [r2]
+            ld r5, [r2]
+            add r5, r1
+            push r5
+            pop r6
+            ldl r7, done
+            jmp [r7]
+            ldl r6, 99
+        done:
+            hlt
+        ",
+        // subroutine calls
+        "
+            ldl r0, 3
+            call double
+            call double
+            hlt
+        double:
+            add r0, r0
+            ret
+        ",
+        // signed compare chain
+        "
+            ldh r0, 0xff
+            ldl r1, 1
+            cmp r0, r1
+            jlt less
+            ldl r2, 1
+        less:
+            jge skip
+            ldl r3, 1
+        skip:
+            hlt
+        ",
+    ];
+
+    /// The state `pipeline` ended in, with the CPU's cycle count in place of
+    /// the pipeline's.
+    fn snapshot(pipeline: &Pipeline, reference: &CpuEmu) -> MachineState {
+        let mut state = pipeline.cpu().snapshot();
+        assert_eq!(state.cycles, pipeline.stats().cycles);
+        state.cycles = reference.cycles();
+        state
+    }
+
+    /// Runs `source` on both models after `setup`, checking they end in the
+    /// same state, with the same fault, having completed the same instructions.
+    fn assert_matches(source: &str, setup: fn(&mut CpuEmu)) {
+        let cpu = || {
+            let mut cpu = CpuEmu::new(assemble(source).unwrap());
+            setup(&mut cpu);
+            cpu
+        };
+        let mut reference = cpu();
+        let expected = reference.run().err();
+
+        for hazards in [Hazards::Forwarding, Hazards::Stalling] {
+            let mut pipeline = Pipeline::new(cpu(), hazards);
+            let result = pipeline.run(|_| {});
+            let context = format!("{:?}\n{}", hazards, source);
+            assert_eq!(result.err(), expected, "{}", context);
+            assert_eq!(
+                snapshot(&pipeline, &reference),
+                reference.snapshot(),
+                "{}",
+                context
+            );
+            if expected.is_none() {
+                assert_eq!(
+                    pipeline.stats().retired,
+                    reference.executed(),
+                    "{}",
+                    context
+                );
+            }
+        }
+    }
+
+    fn vectors(cpu: &mut CpuEmu) {
+        cpu.set_vectors(Some(0xe0)).unwrap();
+    }
+
+    #[test]
+    fn test_matches_cpu_emu() {
+        for source in PROGRAMS {
+            assert_matches(source, |_| {});
+        }
+    }
+
+    #[test]
+    fn test_interrupts_match_cpu_emu() {
+        // A periodic timer on line 0 interrupts a busy loop three times.
+        let timer = "
+            ldl r0, tick
+            st r0, 0xe1
+            ldl r5, 0x10
+            ldh r5, 0xff
+            ldl r6, 0x12
+            ldh r6, 0xff
+            ldl r0, 40
+            st r0, [r5]
+            ldl r0, 7
+            st r0, [r6]
+            ldl r3, 1
+            ldl r4, 3
+            ei
+        wait:
+            cmp r7, r4
+            jne wait
+            di
+            hlt
+        tick:
+            add r7, r3
+            reti
+        ";
+        let cpu = || {
+            let mut cpu = CpuEmu::new(assemble(timer).unwrap());
+            vectors(&mut cpu);
+            cpu.bus_mut()
+                .attach(TIMER_BASE..TIMER_BASE + timer::LEN, Timer::new(0));
+            cpu
+        };
+        let mut reference = cpu();
+        reference.run().unwrap();
+        let mut pipeline = Pipeline::new(cpu(), Hazards::Forwarding);
+        let mut records = Vec::new();
+        pipeline.run(|record| records.push(record.clone())).unwrap();
+        // Started as `st r0, [r6]` executes in cycle 12, the timer expires
+        // every 40 pipeline cycles: in 52, 92 and 132. The jump back flushed
+        // what would have entered EX in 132 and 133, so the last is taken in
+        // 134. The loop ran a different number of times than under
+        // `CpuEmu::run`, but ends the same way.
+        let taken: Vec<u64> = records
+            .iter()
+            .filter(|record| record.notes.contains(&"interrupt".to_string()))
+            .map(|record| record.cycle)
+            .collect();
+        assert_eq!(taken, [52, 92, 134]);
+        assert_eq!(snapshot(&pipeline, &reference), reference.snapshot());
+
+        // An interrupt raised by the host before the first instruction.
+        let raised = "
+            ldl r0, handler
+            st r0, 0xe4
+            ei
+            ldl r1, 1
+            ldl r2, 2
+            hlt
+        handler:
+            ldl r3, 3
+            reti
+        ";
+        assert_matches(raised, |cpu| {
+            vectors(cpu);
+            cpu.interrupts_mut().raise(3).unwrap();
+        });
+
+        let mut cpu = CpuEmu::new(assemble(raised).unwrap());
+        vectors(&mut cpu);
+        cpu.interrupts_mut().raise(3).unwrap();
+        let mut pipeline = Pipeline::new(cpu, Hazards::Forwarding);
+        let mut records = Vec::new();
+        pipeline.run(|record| records.push(record.clone())).unwrap();
+        // Taken as `ldl r1, 1` enters EX; it is squashed and refetched after `reti`.
+        assert_eq!(records[5].notes, vec!["interrupt"]);
+        assert_eq!(records[5].stages[EX], None);
+        assert_eq!(pipeline.cpu().register(Slot::Reg3), 3);
+        assert_eq!(pipeline.stats().retired, 8);
+    }
+
+    #[test]
+    fn test_traps_match_cpu_emu() {
+        let illegal = "
+            ldl r0, trap
+            st r0, 0xe0
+            ldl r1, 1
+            .word 0xf800
+            add r1, r1
+            hlt
+        trap:
+            ldl r2, 9
+            reti
+        ";
+        assert_matches(illegal, vectors);
+
+        let overflow = "
+            ldl r0, trap
+            st r0, 0xe0
+            ldl r1, 1
+            sub r2, r1
+            add r1, r1
+            hlt
+        trap:
+            ldl r3, 1
+            reti
+        ";
+        assert_matches(overflow, |cpu| {
+            vectors(cpu);
+            cpu.set_arithmetic(Arithmetic::Trapping);
+        });
+        assert_matches(overflow, |cpu| cpu.set_arithmetic(Arithmetic::Trapping));
+
+        // An indirect load past the ROM and a pop from an empty stack.
+        let indirect = "
+            ldl r0, trap
+            st r0, 0xe0
+            ldl r1, 0
+            ldh r1, 0x90
+            ld r2, [r1]
+            pop r3
+            hlt
+        trap:
+            add r4, r0
+            reti
+        ";
+        assert_matches(indirect, vectors);
+        assert_matches(indirect, |_| {});
+        assert_matches("ldl r1, 1\npop r3\nhlt", |_| {});
+    }
+
+    #[test]
+    fn test_faults_match_cpu_emu() {
+        let source = "ldl r0, 1\nst r0, [r0]\n.word 0xf800\nhlt";
+        let mut reference = CpuEmu::new(assemble(source).unwrap());
+        let expected = reference.run().unwrap_err();
+
+        let mut pipeline = pipeline(source, Hazards::Forwarding);
+        assert_eq!(pipeline.run(|_| {}), Err(expected));
+        assert_eq!(snapshot(&pipeline, &reference), reference.snapshot());
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints_stop() {
+        let source = "ldl r0, 1\nldl r1, 2\nst r1, 64\nldl r2, 3\nhlt";
+        let run = |setup: fn(&mut CpuEmu)| {
+            let mut cpu = CpuEmu::new(assemble(source).unwrap());
+            setup(&mut cpu);
+            let mut pipeline = Pipeline::new(cpu, Hazards::Forwarding);
+            let mut records = Vec::new();
+            pipeline.run(|record| records.push(record.clone())).unwrap();
+            (pipeline, records)
+        };
+
+        // `ldl r0, 1` runs and drains; `ldl r1, 2` never enters EX.
+        let (pipeline, records) = run(|cpu| {
+            cpu.add_breakpoint(1);
+        });
+        assert_eq!(pipeline.stopped(), Some(&StopReason::Breakpoint(1)));
+        assert_eq!(records[2].notes, vec!["breakpoint at 1"]);
+        assert_eq!(records.len(), 5);
+        assert_eq!(pipeline.stats().retired, 1);
+        assert_eq!(pipeline.cpu().pc(), 1);
+        assert_eq!(pipeline.cpu().register(Slot::Reg0), 1);
+        assert_eq!(pipeline.cpu().register(Slot::Reg1), 0);
+
+        // A breakpoint on the first instruction stops it in EX, unexecuted.
+        let (pipeline, records) = run(|cpu| {
+            cpu.add_breakpoint(0);
+        });
+        assert_eq!(pipeline.stopped(), Some(&StopReason::Breakpoint(0)));
+        assert_eq!(records[2].notes, vec!["breakpoint at 0"]);
+        assert_eq!(records[2].stages[EX], None);
+        assert_eq!(pipeline.stats().retired, 0);
+        assert_eq!(pipeline.cpu().register(Slot::Reg0), 0);
+
+        // The store completes and retires; nothing after it runs.
+        let (pipeline, records) = run(|cpu| {
+            cpu.add_watchpoint(Watchpoint::Write(64));
+        });
+        assert!(matches!(
+            pipeline.stopped(),
+            Some(StopReason::Watchpoint(hit)) if hit.pc == 2
+        ));
+        assert_eq!(
+            records[4].notes,
+            vec!["forward: r1", "watchpoint: write ram[64]"]
+        );
+        assert_eq!(pipeline.stats().retired, 3);
+        assert_eq!(pipeline.cpu().ram(64..65), Ok(&[2][..]));
+        assert_eq!(pipeline.cpu().register(Slot::Reg2), 0);
+    }
+
+    #[test]
+    fn test_no_hazards() {
+        let (pipeline, _) = run("ldl r0, 1\nldl r1, 2\nldl r2, 3\nhlt", Hazards::Stalling);
+        // Four instructions fill and drain the five stages.
+        assert_eq!(
+            pipeline.stats(),
+            PipelineStats {
+                cycles: 8,
+                retired: 4,
+                stalls: 0,
+                flushed: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_data_hazards() {
+        let dependent = "ldl r1, 1\nadd r2, r1\nhlt";
+        let (pipeline, records) = run(dependent, Hazards::Forwarding);
+        assert_eq!(pipeline.stats().stalls, 0);
+        assert_eq!(records[3].notes, vec!["forward: r1"]);
+
+        let (pipeline, records) = run(dependent, Hazards::Stalling);
+        assert_eq!(pipeline.stats().stalls, 2);
+        assert_eq!(records[3].notes, vec!["stall: r1"]);
+
+        let load_use = "ld r1, 0\nadd r2, r1\nhlt";
+        let (pipeline, _) = run(load_use, Hazards::Forwarding);
+        assert_eq!(pipeline.stats().stalls, 1);
+    }
+
+    #[test]
+    fn test_flush() {
+        let (pipeline, records) = run("jmp 3\nldl r0, 1\nldl r1, 1\nhlt", Hazards::Forwarding);
+        assert_eq!(pipeline.stats().flushed, 2);
+        assert_eq!(pipeline.stats().retired, 2);
+        assert_eq!(pipeline.cpu().register(Slot::Reg0), 0);
+        assert_eq!(records[3].notes, vec!["flush 2"]);
+    }
+
+    #[test]
+    fn test_diagram() {
+        let (_, records) = run("ldl r1, 1\nadd r2, r1\nhlt", Hazards::Stalling);
+        let lines: Vec<String> = records.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            CycleRecord::header(),
+            "cycle  IF              ID              EX              MEM             WB"
+        );
+        assert_eq!(
+            lines[..4],
+            [
+                "    1    0 ldl r1, 1   -               -               -               -",
+                "    2    1 add r2, r1    0 ldl r1, 1   -               -               -",
+                "    3    2 hlt           1 add r2, r1    0 ldl r1, 1   -               -",
+                "    4    2 hlt           1 add r2, r1  -                 0 ldl r1, 1   -               stall: r1",
+            ]
+        );
+    }
+}